@@ -1,6 +1,5 @@
-use web_sys::WebGl2RenderingContext;
+use wasm_bindgen::JsCast;
 use yew::prelude::*;
-use yew_canvas::Canvas;
 
 mod rander;
 mod wgpu_state;
@@ -8,66 +7,338 @@ mod resources;
 
 #[function_component(App)]
 fn app() -> Html {
-    let is_hold_state = use_state(|| false);
-    let cursor_move_state = use_state(|| (0i32, 0i32));
-    let cursor_to_state = use_state(|| (0i32, 0i32));
-
-    let onmousedown = {
-        let is_hold_state = is_hold_state.clone();
-        let cursor_move_state = cursor_move_state.clone();
-        Callback::from(move |e: MouseEvent| {
-            let cursor = (e.screen_x(), e.screen_y());
-            cursor_move_state.set(cursor);
-
-            is_hold_state.set(true);
+    let view_state = use_state(rander::camera::View::default);
+    let gestures = use_mut_ref(rander::gesture::Gestures::default);
+    let shader_generation_state = use_state(|| 0u32);
+    let shader_error_state = use_state(|| None::<String>);
+    let click_state = use_state(|| None::<rander::picking::Click>);
+    // Look at whatever the next pick hits
+    let focus_state = use_state(|| false);
+    let selected_state = use_state(|| None::<rander::picking::Pick>);
+    let gizmo_state = use_state(|| Some(rander::gizmo::GizmoMode::Translate));
+    let pointer_state = use_state(|| None::<rander::gizmo::Pointer>);
+    let gizmo_drag_state = use_state(|| false);
+    let save_generation_state = use_state(|| 0u32);
+    let playing_state = use_state(|| true);
+    let seek_state = use_state(|| None::<rander::animation::Seek>);
+    let light_state = use_state(|| None::<rander::scene::SceneLight>);
+    let spawn_state = use_state(|| None::<rander::command::Spawn>);
+    let grading_state = use_state(|| false);
+
+    let on_gesture = {
+        use rander::gesture::Gesture;
+
+        let view_state = view_state.clone();
+        let click_state = click_state.clone();
+        let focus_state = focus_state.clone();
+        let gizmo_dragging = *gizmo_drag_state;
+        Callback::from(move |gesture: Gesture| {
+            let click = |position: (f32, f32)| {
+                click_state.set(Some(rander::picking::Click {
+                    generation: click_state.map_or(1, |click| click.generation + 1),
+                    x: position.0 as i32,
+                    y: position.1 as i32,
+                }))
+            };
+            match gesture {
+                // A gizmo drag moves the instance, not the camera
+                Gesture::Orbit { .. } if gizmo_dragging => {}
+                Gesture::Tap { position } => click(position),
+                // Double tap to look at what is under it
+                Gesture::DoubleTap { position } => {
+                    focus_state.set(true);
+                    click(position);
+                }
+                _ => view_state.set(gesture.move_view(*view_state)),
+            }
+        })
+    };
+
+    let onpointerdown = {
+        let gestures = gestures.clone();
+        let pointer_state = pointer_state.clone();
+        Callback::from(move |e: PointerEvent| {
+            // Keep getting the pointer's events when it leaves the canvas
+            if let Some(target) = e
+                .current_target()
+                .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+            {
+                let _ = target.set_pointer_capture(e.pointer_id());
+            }
+
+            gestures
+                .borrow_mut()
+                .down(e.pointer_id(), position(&e), e.time_stamp());
+            if e.is_primary() {
+                pointer_state.set(Some(rander::gizmo::Pointer {
+                    x: e.offset_x(),
+                    y: e.offset_y(),
+                    pressed: true,
+                }));
+            }
+        })
+    };
+
+    let onpointermove = {
+        let gestures = gestures.clone();
+        let pointer_state = pointer_state.clone();
+        let on_gesture = on_gesture.clone();
+        Callback::from(move |e: PointerEvent| {
+            if e.is_primary() {
+                pointer_state.set(Some(rander::gizmo::Pointer {
+                    x: e.offset_x(),
+                    y: e.offset_y(),
+                    pressed: e.buttons() & 1 != 0,
+                }));
+            }
+
+            let gesture = gestures.borrow_mut().moved(e.pointer_id(), position(&e));
+            if let Some(gesture) = gesture {
+                on_gesture.emit(gesture);
+            }
+        })
+    };
+
+    let onpointerup = {
+        let gestures = gestures.clone();
+        let pointer_state = pointer_state.clone();
+        let on_gesture = on_gesture.clone();
+        Callback::from(move |e: PointerEvent| {
+            if e.is_primary() {
+                pointer_state.set(Some(rander::gizmo::Pointer {
+                    x: e.offset_x(),
+                    y: e.offset_y(),
+                    pressed: false,
+                }));
+            }
+
+            let gesture = gestures
+                .borrow_mut()
+                .up(e.pointer_id(), position(&e), e.time_stamp());
+            if let Some(gesture) = gesture {
+                on_gesture.emit(gesture);
+            }
         })
     };
 
-    let onmouseup = {
-        let is_hold_state = is_hold_state.clone();
-        Callback::from(move |_| {
-            is_hold_state.set(false);
+    let onpointercancel = {
+        let pointer_state = pointer_state.clone();
+        Callback::from(move |e: PointerEvent| {
+            gestures.borrow_mut().cancel(e.pointer_id());
+            if e.is_primary() {
+                pointer_state.set(Some(rander::gizmo::Pointer {
+                    x: e.offset_x(),
+                    y: e.offset_y(),
+                    pressed: false,
+                }));
+            }
         })
     };
 
-    let onmousemove = {
-        let cursor_before = *cursor_move_state.clone();
-        let cursor_to_state = cursor_to_state.clone();
+    // The mouse wheel zooms like a pinch
+    let onwheel = Callback::from(move |e: WheelEvent| {
+        on_gesture.emit(rander::gesture::Gesture::PanZoom {
+            pan: (0.0, 0.0),
+            zoom: (-e.delta_y() * 0.001).exp() as f32,
+        });
+    });
 
-        Callback::from(move |e: MouseEvent| {
-            if *is_hold_state {
-                let cursor = (e.screen_x(), e.screen_y());
-                cursor_to_state.set((cursor_before.0 - cursor.0, cursor_before.1 - cursor.1));
+    let on_pick = {
+        let selected_state = selected_state.clone();
+        let view_state = view_state.clone();
+        let focus = *focus_state;
+        let focus_state = focus_state.clone();
+        Callback::from(move |pick: Option<rander::picking::Pick>| {
+            if focus {
+                focus_state.set(false);
+                if let Some(pick) = &pick {
+                    view_state.set(rander::camera::View {
+                        focus: Some(pick.position),
+                        pan: (0.0, 0.0),
+                        ..*view_state
+                    });
+                }
+            }
+            selected_state.set(pick);
+        })
+    };
+
+    let on_gizmo_drag = {
+        let gizmo_drag_state = gizmo_drag_state.clone();
+        Callback::from(move |dragging| gizmo_drag_state.set(dragging))
+    };
+
+    let on_save = Callback::from(|json: String| download("scene.json", &json));
+
+    let on_shader_error = {
+        let shader_error_state = shader_error_state.clone();
+        Callback::from(move |error| shader_error_state.set(error))
+    };
+
+    let onkeydown = {
+        let shader_generation_state = shader_generation_state.clone();
+        let gizmo_state = gizmo_state.clone();
+        let save_generation_state = save_generation_state.clone();
+        let playing_state = playing_state.clone();
+        let seek_state = seek_state.clone();
+        let light_state = light_state.clone();
+        let spawn_state = spawn_state.clone();
+        let selected_state = selected_state.clone();
+        let grading_state = grading_state.clone();
+        Callback::from(move |e: KeyboardEvent| {
+            use rander::gizmo::GizmoMode;
+
+            match e.key().as_str() {
+                // Press `R` to hot-reload the shaders.
+                "r" | "R" => shader_generation_state.set(*shader_generation_state + 1),
+                // `1`, `2` and `3` pick the gizmo, `0` hides it.
+                "1" => gizmo_state.set(Some(GizmoMode::Translate)),
+                "2" => gizmo_state.set(Some(GizmoMode::Rotate)),
+                "3" => gizmo_state.set(Some(GizmoMode::Scale)),
+                "0" => gizmo_state.set(None),
+                // `S` downloads the scene with the moved instances.
+                "s" | "S" => save_generation_state.set(*save_generation_state + 1),
+                // `Space` pauses and resumes the animations, `Home` rewinds them.
+                " " => playing_state.set(!*playing_state),
+                "Home" => seek_state.set(Some(rander::animation::Seek {
+                    generation: seek_state.map_or(1, |seek| seek.generation + 1),
+                    time: 0.0,
+                })),
+                // `G` switches the warm colour grade on and off.
+                "g" | "G" => grading_state.set(!*grading_state),
+                // `L` switches the light between white and warm.
+                "l" | "L" => {
+                    let warm = [1.0, 0.6, 0.3];
+                    let warm_now = light_state
+                        .as_ref()
+                        .map_or(false, |light| light.color == warm);
+                    light_state.set(Some(rander::scene::SceneLight {
+                        position: [2.0, 2.0, 2.0],
+                        color: if warm_now { [1.0, 1.0, 1.0] } else { warm },
+                    }));
+                }
+                // `N` adds an instance where the last click hit, or at the origin.
+                "n" | "N" => spawn_state.set(Some(rander::command::Spawn {
+                    generation: spawn_state.as_ref().map_or(1, |spawn| spawn.generation + 1),
+                    instance: rander::scene::SceneInstance {
+                        position: selected_state
+                            .as_ref()
+                            .map_or([0.0, 0.0, 0.0], |pick| pick.position),
+                        rotation: [0.0, 0.0, 0.0, 1.0],
+                        scale: [1.0, 1.0, 1.0],
+                    },
+                })),
+                _ => {}
             }
         })
     };
 
     let rander = rander::Rander {
-        cursor_to: *cursor_to_state,
+        view: *view_state,
+        shader_generation: *shader_generation_state,
+        on_shader_error,
+        sample_count: 4,
+        max_pixel_ratio: 2.0,
+        scene: "scene.json".to_string(),
+        gpu_culling: true,
+        exposure: 1.0,
+        tonemapping: rander::hdr::Tonemapping::Aces,
+        background: rander::background::Background::Skybox,
+        post_effects: [
+            Some(rander::post::Effect::Bloom),
+            grading_state.then(|| rander::post::Effect::ColorGrade),
+            Some(rander::post::Effect::Vignette),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        click: *click_state,
+        on_pick,
+        selection: selected_state.iter().map(|pick| pick.instance).collect(),
+        outline: rander::outline::Outline {
+            color: [1.0, 0.5, 0.0],
+            width: 3.0,
+        },
+        gizmo: *gizmo_state,
+        pointer: *pointer_state,
+        on_gizmo_drag,
+        save_generation: *save_generation_state,
+        on_save,
+        playing: *playing_state,
+        seek: *seek_state,
+        light: (*light_state).clone(),
+        spawn: (*spawn_state).clone(),
+        lut: Some("lut-warm.png".to_string()),
+    };
+
+    let selected = match &*selected_state {
+        Some(pick) => format!(
+            "{} / {} (mesh {}) / instance {} at ({:.2}, {:.2}, {:.2})",
+            pick.model,
+            pick.mesh_name,
+            pick.mesh,
+            pick.instance,
+            pick.position[0],
+            pick.position[1],
+            pick.position[2],
+        ),
+        None => "Nothing selected".to_string(),
     };
 
     html!(
         <div
-            {onmousedown}
-            {onmouseup}
-            {onmousemove}
+            {onpointerdown}
+            {onpointermove}
+            {onpointerup}
+            {onpointercancel}
+            {onwheel}
+            {onkeydown}
+            tabindex="0"
             style="
                 width: 100%;
                 height: 100%;
+                touch-action: none;
             "
         >
-            <Canvas<WebGl2RenderingContext , rander::Rander>
-                //Just use style, canvas can suit automatically.
-                style="
-                    width: 100%;
-                    height: 100%;
-                "
-                rander={Box::new(rander)}
-            />
+            <rander::Viewport ..rander />
+            <div style="
+                position: absolute;
+                top: 8px;
+                left: 8px;
+                color: white;
+                font-family: monospace;
+                pointer-events: none;
+            ">
+                {selected}
+                if let Some(error) = &*shader_error_state {
+                    <pre style="color: red; white-space: pre-wrap;">{error}</pre>
+                }
+            </div>
         </div>
     )
 }
 
+/// Where a pointer is over the canvas, in CSS pixels.
+fn position(e: &PointerEvent) -> (f32, f32) {
+    (e.offset_x() as f32, e.offset_y() as f32)
+}
+
+/// Hands `text` to the browser as a file download.
+fn download(file_name: &str, text: &str) {
+    let url = gloo::file::ObjectUrl::from(gloo::file::Blob::new_with_options(
+        text,
+        Some("application/json"),
+    ));
+    let anchor = gloo::utils::document()
+        .create_element("a")
+        .unwrap()
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .unwrap();
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+}
+
 fn main() {
     yew::start_app::<App>();
 }
@@ -5,46 +5,415 @@ pub(super) mod texture;
 pub(super) mod light;
 pub(super) mod vertex;
 pub(super) mod shader;
+pub(super) mod pipeline;
+pub(super) mod hdr;
+pub(super) mod post;
+pub(super) mod environment;
+pub(super) mod background;
+pub(super) mod culling;
+pub(super) mod lod;
+pub(super) mod gpu_culling;
+pub(super) mod picking;
+pub(super) mod outline;
+pub(super) mod gizmo;
+pub(super) mod scene;
+pub(super) mod keyframes;
+pub(super) mod skeleton;
+pub(super) mod skinned;
+pub(super) mod animation;
+pub(super) mod frame;
+pub(super) mod resize;
+pub(super) mod command;
+pub(super) mod gesture;
+
+use std::{
+    cell::RefCell,
+    mem,
+    rc::{Rc, Weak},
+};
 
-use once_cell::sync::OnceCell;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::HtmlCanvasElement;
-use yew_canvas::WithRander;
+use yew::prelude::*;
 
 use crate::wgpu_state::State;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Properties)]
 pub(super) struct Rander {
-    pub(crate) cursor_to: (i32, i32),
+    /// Where the user moved the camera to, see `gesture::Gestures`.
+    pub(crate) view: camera::View,
+    /// Bump this to refetch and rebuild the shaders.
+    pub(crate) shader_generation: u32,
+    /// Called with why the shaders failed to reload, or `None` once they
+    /// reload fine again.
+    pub(crate) on_shader_error: Callback<Option<String>>,
+    /// MSAA sample count, 1 or 4. Only read when the renderer is created.
+    pub(crate) sample_count: u32,
+    /// Caps the device pixels drawn per CSS pixel, as HiDPI screens can
+    /// have three or more.
+    pub(crate) max_pixel_ratio: f64,
+    /// Scene file under `static/`, see `scene::Scene`. Only read when the
+    /// renderer is created.
+    pub(crate) scene: String,
+    /// Cull and pick levels of detail in a compute shader when the adapter
+    /// can, on the CPU otherwise. Only read when the renderer is created.
+    pub(crate) gpu_culling: bool,
+    pub(crate) exposure: f32,
+    pub(crate) tonemapping: hdr::Tonemapping,
+    pub(crate) background: background::Background,
+    /// Post-processing effects to run, the rest are switched off.
+    pub(crate) post_effects: Vec<post::Effect>,
+    /// The last click on the canvas, picked once per generation.
+    pub(crate) click: Option<picking::Click>,
+    /// Called with what is under each new `click`, `None` for the background.
+    pub(crate) on_pick: Callback<Option<picking::Pick>>,
+    /// Indices of the instances to outline.
+    pub(crate) selection: Vec<usize>,
+    pub(crate) outline: outline::Outline,
+    /// Handles drawn on the first selected instance, `None` hides them.
+    pub(crate) gizmo: Option<gizmo::GizmoMode>,
+    pub(crate) pointer: Option<gizmo::Pointer>,
+    /// Called with `true` when a gizmo handle starts being dragged and
+    /// `false` when it is let go.
+    pub(crate) on_gizmo_drag: Callback<bool>,
+    /// Bump this to save the scene as it is now into `on_save`.
+    pub(crate) save_generation: u32,
+    /// Called with the saved scene as JSON.
+    pub(crate) on_save: Callback<String>,
+    /// Play or pause the scene's animations and the skinned models' clips.
+    pub(crate) playing: bool,
+    pub(crate) seek: Option<animation::Seek>,
+    /// Replaces the scene's first light, until its animations move it.
+    pub(crate) light: Option<scene::SceneLight>,
+    /// The last instance to add, added once per generation.
+    pub(crate) spawn: Option<command::Spawn>,
+    /// LUT strip under `static/` for `post::Effect::ColorGrade`, see
+    /// `texture::Texture::lut_from_image`. Grades nothing when not set.
+    pub(crate) lut: Option<String>,
 }
 
-static mut WGPU_STATE: OnceCell<State> = OnceCell::new();
+#[derive(Default)]
+enum Renderer {
+    #[default]
+    Unloaded,
+    /// `State::new` or `State::reload_shaders` has it.
+    Busy,
+    Ready(Box<State>),
+    /// Failed to load, see the console.
+    Failed,
+}
 
-impl WithRander for Rander {
-    fn rand(self, canvas: &HtmlCanvasElement) {
-        let canvas = canvas.clone();
-        let (height, width) = (canvas.height(), canvas.width());
+/// What a `Viewport` keeps between renders, shared with its frame loop and
+/// the futures that load it.
+#[derive(Default)]
+struct ViewportState {
+    renderer: Renderer,
+    frame_loop: Option<frame::FrameLoop>,
+    size_observer: Option<resize::SizeObserver>,
+    /// The latest props, caught up on once the renderer is ready.
+    props: Option<Rander>,
+    /// The props the renderer was last brought up to date with.
+    shown: Option<Rander>,
+    /// A LUT that finished loading while the renderer was busy, and the
+    /// file it came from.
+    lut: Option<(String, texture::TextureImage)>,
+}
 
-        unsafe {
-            if WGPU_STATE.get().is_none() {
-                spawn_local(async move {
-                    let state = State::new(&canvas).await;
-                    WGPU_STATE.get_or_init(|| state.unwrap());
+/// A canvas filling its parent, with a renderer of its own for the scene
+/// in its props. Each viewport gets its own device too, on WebGL2 a device
+/// only draws to the canvas whose context it came from.
+#[function_component(Viewport)]
+pub(super) fn viewport(props: &Rander) -> Html {
+    let canvas_ref = use_node_ref();
+    let viewport = use_mut_ref(ViewportState::default);
 
-                    //render pass per 17 ms
-                    gloo::timers::callback::Interval::new(17, || {
-                        WGPU_STATE.get_mut().unwrap().render().unwrap();
-                    })
-                    .forget();
+    {
+        let canvas_ref = canvas_ref.clone();
+        let viewport = viewport.clone();
+        use_effect_with_deps(
+            move |_| {
+                let canvas = canvas_ref.cast::<HtmlCanvasElement>().unwrap();
+                let size_observer = resize::SizeObserver::new(&canvas, {
+                    let viewport = Rc::downgrade(&viewport);
+                    let canvas = canvas.clone();
+                    move || {
+                        if let Some(viewport) = viewport.upgrade() {
+                            fit(&viewport, &canvas);
+                        }
+                    }
                 });
+                viewport.borrow_mut().size_observer = Some(size_observer);
+
+                move || {
+                    let mut viewport = viewport.borrow_mut();
+                    viewport.size_observer = None;
+                    viewport.frame_loop = None;
+                    viewport.renderer = Renderer::Unloaded;
+                    viewport.shown = None;
+                }
+            },
+            (),
+        );
+    }
+
+    {
+        let canvas_ref = canvas_ref.clone();
+        let viewport = viewport.clone();
+        let props = props.clone();
+        use_effect(move || {
+            let canvas = canvas_ref.cast::<HtmlCanvasElement>().unwrap();
+            viewport.borrow_mut().props = Some(props);
+            fit(&viewport, &canvas);
+            update(&viewport, &canvas);
+            || ()
+        });
+    }
+
+    html! {
+        <canvas style="width: 100%; height: 100%;" ref={canvas_ref} />
+    }
+}
+
+/// Size the canvas' backing store to match it on the page, and the renderer
+/// along with it.
+fn fit(viewport: &Rc<RefCell<ViewportState>>, canvas: &HtmlCanvasElement) {
+    let mut shared = viewport.borrow_mut();
+    let max_pixel_ratio = match &shared.props {
+        Some(props) => props.max_pixel_ratio,
+        None => return,
+    };
+    let max_size = match &shared.renderer {
+        Renderer::Ready(state) => state.max_size(),
+        _ => resize::DEFAULT_MAX_SIZE,
+    };
+
+    let (width, height) = resize::backing_size(
+        (canvas.client_width(), canvas.client_height()),
+        gloo::utils::window().device_pixel_ratio(),
+        max_pixel_ratio,
+        max_size,
+    );
+    if (canvas.width(), canvas.height()) != (width, height) {
+        canvas.set_width(width);
+        canvas.set_height(height);
+    }
+    if let Renderer::Ready(state) = &mut shared.renderer {
+        send(state, command::Command::Resize { width, height });
+    }
+}
+
+/// Hand the latest props to the renderer, loading it first if need be.
+fn update(viewport: &Rc<RefCell<ViewportState>>, canvas: &HtmlCanvasElement) {
+    let mut shared = viewport.borrow_mut();
+    let props = match &shared.props {
+        Some(props) => props.clone(),
+        None => return,
+    };
+
+    let events = match shared.renderer {
+        Renderer::Unloaded => {
+            shared.renderer = Renderer::Busy;
+            spawn_local(load(Rc::downgrade(viewport), canvas.clone(), props));
+            return;
+        }
+        Renderer::Busy | Renderer::Failed => return,
+        Renderer::Ready(_) => {
+            let reload = shared.shown.as_ref().map_or(false, |shown| {
+                shown.shader_generation != props.shader_generation
+            });
+            if shared
+                .shown
+                .as_ref()
+                .map_or(true, |shown| shown.lut != props.lut)
+            {
+                if let Some(file_name) = &props.lut {
+                    spawn_local(load_lut(Rc::downgrade(viewport), file_name.clone()));
+                }
+            }
+            let events = apply(&mut shared, canvas, &props);
+            if reload {
+                if let Renderer::Ready(state) = mem::replace(&mut shared.renderer, Renderer::Busy) {
+                    spawn_local(reload_shaders(
+                        Rc::downgrade(viewport),
+                        canvas.clone(),
+                        state,
+                        props.on_shader_error.clone(),
+                    ));
+                }
             }
+            events
+        }
+    };
+    // The callbacks may render right away, which needs the state again
+    drop(shared);
+
+    for event in events {
+        match event {
+            command::Event::Picked(pick) => props.on_pick.emit(pick),
+            command::Event::GizmoDrag(dragging) => props.on_gizmo_drag.emit(dragging),
+            command::Event::Saved(json) => props.on_save.emit(json),
+        }
+    }
+}
+
+/// Send the renderer the commands for whatever changed in `props` since it
+/// was last shown any.
+fn apply(
+    shared: &mut ViewportState,
+    canvas: &HtmlCanvasElement,
+    props: &Rander,
+) -> Vec<command::Event> {
+    let state = match &mut shared.renderer {
+        Renderer::Ready(state) => state,
+        _ => return Vec::new(),
+    };
+
+    let (height, width) = (canvas.height(), canvas.width());
+    let (client_height, client_width) = (canvas.client_height(), canvas.client_width());
+    // CSS pixels to canvas pixels
+    let to_canvas = |x: i32, y: i32| {
+        (
+            x as f32 * width as f32 / client_width.max(1) as f32,
+            y as f32 * height as f32 / client_height.max(1) as f32,
+        )
+    };
 
-            if !WGPU_STATE.get().is_none() {
-                WGPU_STATE
-                    .get_mut()
-                    .unwrap()
-                    .update(width, height, self.cursor_to);
-                WGPU_STATE.get_mut().unwrap().render().unwrap();
+    let mut commands = command::from_props(shared.shown.as_ref(), props, to_canvas);
+    if let Some((file_name, image)) = shared.lut.take() {
+        // Unless the prop moved on while it waited
+        if props.lut.as_ref() == Some(&file_name) {
+            commands.insert(0, command::Command::SetLut(Some(image)));
+        }
+    }
+    shared.shown = Some(props.clone());
+    commands
+        .into_iter()
+        .filter_map(|command| send(state, command))
+        .collect()
+}
+
+/// Hand `command` to `state`, logging it if it fails.
+fn send(state: &mut State, command: command::Command) -> Option<command::Event> {
+    match state.handle(command) {
+        Ok(event) => event,
+        Err(err) => {
+            gloo::console::error!(format!("Renderer command failed:\n{}", err));
+            None
+        }
+    }
+}
+
+async fn load(viewport: Weak<RefCell<ViewportState>>, canvas: HtmlCanvasElement, props: Rander) {
+    let state = match scene::Scene::from_file_name(&props.scene).await {
+        Ok(scene) => State::new(&canvas, props.sample_count, &scene, props.gpu_culling).await,
+        Err(err) => Err(err),
+    };
+    // Unmounted while loading
+    let viewport = match viewport.upgrade() {
+        Some(viewport) => viewport,
+        None => return,
+    };
+
+    match state {
+        Ok(state) => ready(&viewport, &canvas, Box::new(state)),
+        Err(err) => {
+            gloo::console::error!(format!("Renderer failed to load:\n{}", err));
+            viewport.borrow_mut().renderer = Renderer::Failed;
+        }
+    }
+}
+
+/// Fetch a colour grading LUT and hand it to the renderer, or keep it for
+/// when the renderer is ready again.
+async fn load_lut(viewport: Weak<RefCell<ViewportState>>, file_name: String) {
+    let image = match texture::TextureImage::from_file_name(&file_name).await {
+        Ok(image) => image,
+        Err(err) => {
+            gloo::console::error!(format!("LUT failed to load:\n{}", err));
+            return;
+        }
+    };
+    let viewport = match viewport.upgrade() {
+        Some(viewport) => viewport,
+        None => return,
+    };
+
+    let mut shared = viewport.borrow_mut();
+    // Another LUT was asked for while this one loaded
+    let wanted = shared.props.as_ref().and_then(|props| props.lut.as_ref());
+    if wanted != Some(&file_name) {
+        return;
+    }
+    match &mut shared.renderer {
+        Renderer::Ready(state) => {
+            send(state, command::Command::SetLut(Some(image)));
+        }
+        Renderer::Busy => shared.lut = Some((file_name, image)),
+        Renderer::Unloaded | Renderer::Failed => {}
+    }
+}
+
+async fn reload_shaders(
+    viewport: Weak<RefCell<ViewportState>>,
+    canvas: HtmlCanvasElement,
+    mut state: Box<State>,
+    on_shader_error: Callback<Option<String>>,
+) {
+    let result = state.reload_shaders().await;
+    if let Err(err) = &result {
+        gloo::console::error!(format!("Shader reload failed:\n{}", err));
+    }
+    if let Some(viewport) = viewport.upgrade() {
+        on_shader_error.emit(result.err().map(|err| err.to_string()));
+        ready(&viewport, &canvas, state);
+    }
+}
+
+/// Put `state` back into `viewport`, start drawing it and catch it up on
+/// the props it missed while busy.
+fn ready(viewport: &Rc<RefCell<ViewportState>>, canvas: &HtmlCanvasElement, state: Box<State>) {
+    {
+        let mut shared = viewport.borrow_mut();
+        // Unmounted in the meantime
+        if !matches!(shared.renderer, Renderer::Busy) {
+            return;
+        }
+        shared.renderer = Renderer::Ready(state);
+        if shared.frame_loop.is_none() {
+            shared.frame_loop = Some(frame::FrameLoop::new(draw(Rc::downgrade(viewport))));
+        }
+    }
+    fit(viewport, canvas);
+    update(viewport, canvas);
+}
+
+/// Draws a frame of `viewport` every `delta` seconds.
+fn draw(viewport: Weak<RefCell<ViewportState>>) -> impl FnMut(f32) -> bool {
+    move |delta| {
+        let viewport = match viewport.upgrade() {
+            Some(viewport) => viewport,
+            None => return false,
+        };
+        let mut viewport = viewport.borrow_mut();
+        let state = match &mut viewport.renderer {
+            Renderer::Ready(state) => state,
+            // Drawing again once the shaders have reloaded
+            Renderer::Busy => return true,
+            Renderer::Unloaded | Renderer::Failed => return false,
+        };
+
+        state.advance(delta);
+        match state.render() {
+            Ok(()) => true,
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                state.reconfigure();
+                true
+            }
+            // Try again next frame
+            Err(wgpu::SurfaceError::Timeout) => true,
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                gloo::console::error!("Out of memory, stopped rendering");
+                false
             }
         }
     }
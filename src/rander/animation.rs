@@ -0,0 +1,530 @@
+use anyhow::{anyhow, bail};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    camera::Camera,
+    instance::Instance,
+    keyframes::{Interpolate, Interpolation, Keyframes},
+    light::LightUniform,
+    scene::SceneTrack,
+};
+
+/// How progress from one keyframe to the next speeds up and slows down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    /// Jump at each keyframe.
+    Step,
+}
+
+impl Easing {
+    /// Eased progress for `t` from 0 to 1.
+    pub(crate) fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut if t < 0.5 => 2.0 * t * t,
+            Easing::EaseInOut => 1.0 - 2.0 * (1.0 - t) * (1.0 - t),
+            Easing::Step if t < 1.0 => 0.0,
+            Easing::Step => 1.0,
+        }
+    }
+}
+
+/// What a track does past its last keyframe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Repeat {
+    /// Hold the last keyframe.
+    Once,
+    #[default]
+    Loop,
+    /// Play backwards to the start, then forwards again.
+    PingPong,
+}
+
+impl Repeat {
+    /// Time into a track `duration` seconds long, `time` seconds after it
+    /// started.
+    pub(crate) fn local_time(self, time: f32, duration: f32) -> f32 {
+        if duration <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            Repeat::Once => time.clamp(0.0, duration),
+            Repeat::Loop => time.rem_euclid(duration),
+            Repeat::PingPong => {
+                let time = time.rem_euclid(2.0 * duration);
+                if time > duration {
+                    2.0 * duration - time
+                } else {
+                    time
+                }
+            }
+        }
+    }
+}
+
+/// What a track moves, as written in a scene file: `{ "instance": 3 }`,
+/// `"light"` or `"camera"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Target {
+    /// Index into the first model's instances.
+    Instance(usize),
+    Light,
+    Camera,
+}
+
+/// Which part of a `Target` a track moves. Instances have a position,
+/// rotation and scale, the light a position and colour, and the camera an
+/// eye, target and field of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Property {
+    Position,
+    Rotation,
+    Scale,
+    Color,
+    Eye,
+    Target,
+    Fovy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Channel {
+    InstancePosition(usize, Keyframes<Vector3<f32>>),
+    InstanceRotation(usize, Keyframes<Quaternion<f32>>),
+    InstanceScale(usize, Keyframes<Vector3<f32>>),
+    LightPosition(Keyframes<Vector3<f32>>),
+    LightColor(Keyframes<Vector3<f32>>),
+    CameraEye(Keyframes<Vector3<f32>>),
+    CameraTarget(Keyframes<Vector3<f32>>),
+    /// In degrees.
+    CameraFovy(Keyframes<f32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Track {
+    channel: Channel,
+    easing: Easing,
+    repeat: Repeat,
+}
+
+impl Track {
+    fn from_scene(track: &SceneTrack, instance_count: usize) -> Result<Self, anyhow::Error> {
+        if let Target::Instance(index) = track.target {
+            if index >= instance_count {
+                bail!(
+                    "instance {} is out of range, there are {}",
+                    index,
+                    instance_count
+                );
+            }
+        }
+
+        let channel = match (track.target, track.property) {
+            (Target::Instance(index), Property::Position) => {
+                Channel::InstancePosition(index, keyframes(track, vector)?)
+            }
+            (Target::Instance(index), Property::Rotation) => {
+                Channel::InstanceRotation(index, keyframes(track, rotation)?)
+            }
+            (Target::Instance(index), Property::Scale) => {
+                Channel::InstanceScale(index, keyframes(track, vector)?)
+            }
+            (Target::Light, Property::Position) => {
+                Channel::LightPosition(keyframes(track, vector)?)
+            }
+            (Target::Light, Property::Color) => Channel::LightColor(keyframes(track, vector)?),
+            (Target::Camera, Property::Eye) => Channel::CameraEye(keyframes(track, vector)?),
+            (Target::Camera, Property::Target) => Channel::CameraTarget(keyframes(track, vector)?),
+            (Target::Camera, Property::Fovy) => Channel::CameraFovy(keyframes(track, scalar)?),
+            (target, property) => bail!("{:?} has no {:?} to animate", target, property),
+        };
+
+        Ok(Self {
+            channel,
+            easing: track.easing,
+            repeat: track.repeat,
+        })
+    }
+
+    fn duration(&self) -> f32 {
+        match &self.channel {
+            Channel::InstanceRotation(_, keyframes) => keyframes.duration(),
+            Channel::CameraFovy(keyframes) => keyframes.duration(),
+            Channel::InstancePosition(_, keyframes)
+            | Channel::InstanceScale(_, keyframes)
+            | Channel::LightPosition(keyframes)
+            | Channel::LightColor(keyframes)
+            | Channel::CameraEye(keyframes)
+            | Channel::CameraTarget(keyframes) => keyframes.duration(),
+        }
+    }
+
+    fn apply(
+        &self,
+        time: f32,
+        instances: &mut [Instance],
+        light: &mut LightUniform,
+        camera: &mut Camera,
+    ) {
+        let time = self.repeat.local_time(time, self.duration());
+        let ease = |t| self.easing.apply(t);
+        match &self.channel {
+            Channel::InstancePosition(index, keyframes) => {
+                instances[*index].position = keyframes.sample_eased(time, ease)
+            }
+            Channel::InstanceRotation(index, keyframes) => {
+                instances[*index].rotation = keyframes.sample_eased(time, ease)
+            }
+            Channel::InstanceScale(index, keyframes) => {
+                instances[*index].scale = keyframes.sample_eased(time, ease)
+            }
+            Channel::LightPosition(keyframes) => {
+                light.position = keyframes.sample_eased(time, ease).into()
+            }
+            Channel::LightColor(keyframes) => {
+                light.color = keyframes.sample_eased(time, ease).into()
+            }
+            Channel::CameraEye(keyframes) => {
+                camera.eye = Point3::from_vec(keyframes.sample_eased(time, ease))
+            }
+            Channel::CameraTarget(keyframes) => {
+                camera.target = Point3::from_vec(keyframes.sample_eased(time, ease))
+            }
+            Channel::CameraFovy(keyframes) => camera.fovy = keyframes.sample_eased(time, ease),
+        }
+    }
+}
+
+fn keyframes<T: Interpolate>(
+    track: &SceneTrack,
+    value: fn(&[f32]) -> Option<T>,
+) -> Result<Keyframes<T>, anyhow::Error> {
+    let name = format!("{:?} {:?}", track.target, track.property);
+    let times = track
+        .keyframes
+        .iter()
+        .map(|keyframe| keyframe.time)
+        .collect::<Vec<_>>();
+    if times.windows(2).any(|pair| pair[0] > pair[1]) {
+        bail!("{} keyframes are out of order", name);
+    }
+    let values = track
+        .keyframes
+        .iter()
+        .map(|keyframe| {
+            value(&keyframe.value).ok_or_else(|| {
+                anyhow!(
+                    "{} keyframe at {}s can't have {} components",
+                    name,
+                    keyframe.time,
+                    keyframe.value.len()
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Keyframes::new(times, values, Interpolation::Linear)
+        .ok_or_else(|| anyhow!("{} has no keyframes", name))
+}
+
+fn vector(value: &[f32]) -> Option<Vector3<f32>> {
+    <[f32; 3]>::try_from(value).ok().map(Vector3::from)
+}
+
+/// `[x, y, z, w]`, normalized.
+fn rotation(value: &[f32]) -> Option<Quaternion<f32>> {
+    <[f32; 4]>::try_from(value)
+        .ok()
+        .map(|rotation| Quaternion::from(rotation).normalize())
+}
+
+fn scalar(value: &[f32]) -> Option<f32> {
+    match value {
+        [value] => Some(*value),
+        _ => None,
+    }
+}
+
+/// Keyframed instances, light and camera, sampled by `Clock` time.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Animation {
+    tracks: Vec<Track>,
+}
+
+impl Animation {
+    /// `instance_count` is how many instances the first model has.
+    pub(crate) fn from_scene(
+        tracks: &[SceneTrack],
+        instance_count: usize,
+    ) -> Result<Self, anyhow::Error> {
+        let tracks = tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| {
+                Track::from_scene(track, instance_count)
+                    .map_err(|err| anyhow!("animation {}: {}", i, err))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { tracks })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    pub(crate) fn animates_camera(&self) -> bool {
+        self.tracks.iter().any(|track| {
+            matches!(
+                track.channel,
+                Channel::CameraEye(_) | Channel::CameraTarget(_) | Channel::CameraFovy(_)
+            )
+        })
+    }
+
+    /// Moves everything the tracks animate to where it is `time` seconds
+    /// in, leaving the rest alone. Later tracks win over earlier ones on
+    /// the same property.
+    pub(crate) fn apply(
+        &self,
+        time: f32,
+        instances: &mut [Instance],
+        light: &mut LightUniform,
+        camera: &mut Camera,
+    ) {
+        for track in &self.tracks {
+            track.apply(time, instances, light, camera);
+        }
+    }
+}
+
+/// Jump the animations to `time` seconds in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Seek {
+    /// Bump this on every seek, the same seek only happens once.
+    pub(crate) generation: u32,
+    pub(crate) time: f32,
+}
+
+/// Play time of the animations, which only moves while playing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Clock {
+    /// In seconds.
+    time: f64,
+    playing: bool,
+}
+
+impl Clock {
+    pub(crate) fn new() -> Self {
+        Self {
+            time: 0.0,
+            playing: true,
+        }
+    }
+
+    /// Advance by `delta` seconds, if playing, and return the play time.
+    pub(crate) fn advance(&mut self, delta: f32) -> f32 {
+        if self.playing {
+            self.time += delta as f64;
+        }
+        self.time as f32
+    }
+
+    pub(crate) fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    pub(crate) fn seek(&mut self, time: f32) {
+        self.time = time as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Rotation3};
+
+    use super::*;
+    use crate::rander::scene::{Scene, SceneKeyframe};
+
+    fn track(target: Target, property: Property, keyframes: &[(f32, &[f32])]) -> SceneTrack {
+        SceneTrack {
+            target,
+            property,
+            keyframes: keyframes
+                .iter()
+                .map(|&(time, value)| SceneKeyframe {
+                    time,
+                    value: value.to_vec(),
+                })
+                .collect(),
+            easing: Easing::Linear,
+            repeat: Repeat::Loop,
+        }
+    }
+
+    fn targets() -> (Vec<Instance>, LightUniform, Camera) {
+        let instance = Instance {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::from_axis_angle(Vector3::unit_y(), Deg(0.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        };
+        let light = LightUniform {
+            position: [0.0; 3],
+            _padding: 0,
+            color: [1.0; 3],
+            _padding2: 0,
+        };
+        let camera = Camera {
+            eye: Point3::new(0.0, 0.0, 2.0),
+            target: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        (vec![instance; 2], light, camera)
+    }
+
+    #[test]
+    fn eases_keep_their_ends() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+            Easing::Step,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0, "{:?}", easing);
+            assert_eq!(easing.apply(1.0), 1.0, "{:?}", easing);
+        }
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+        assert_eq!(Easing::Step.apply(0.99), 0.0);
+    }
+
+    #[test]
+    fn repeats_past_the_end() {
+        assert_eq!(Repeat::Once.local_time(3.0, 2.0), 2.0);
+        assert_eq!(Repeat::Loop.local_time(2.5, 2.0), 0.5);
+        assert_eq!(Repeat::PingPong.local_time(2.5, 2.0), 1.5);
+        assert_eq!(Repeat::PingPong.local_time(4.5, 2.0), 0.5);
+        assert_eq!(Repeat::Loop.local_time(1.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn animates_instances_lights_and_camera() {
+        let animation = Animation::from_scene(
+            &[
+                track(
+                    Target::Instance(1),
+                    Property::Position,
+                    &[(0.0, &[0.0, 0.0, 0.0]), (2.0, &[4.0, 0.0, 0.0])],
+                ),
+                track(
+                    Target::Light,
+                    Property::Color,
+                    &[(0.0, &[1.0, 1.0, 1.0]), (1.0, &[0.0, 0.0, 0.0])],
+                ),
+                track(
+                    Target::Camera,
+                    Property::Fovy,
+                    &[(0.0, &[45.0]), (1.0, &[90.0])],
+                ),
+            ],
+            2,
+        )
+        .unwrap();
+        assert!(animation.animates_camera());
+
+        let (mut instances, mut light, mut camera) = targets();
+        animation.apply(0.5, &mut instances, &mut light, &mut camera);
+        assert_eq!(instances[0].position, Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(instances[1].position, Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(light.color, [0.5; 3]);
+        assert_eq!(camera.fovy, 67.5);
+        assert_eq!(camera.eye, Point3::new(0.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn tracks_ease_and_ping_pong() {
+        let mut light_track = track(
+            Target::Light,
+            Property::Position,
+            &[(0.0, &[0.0, 0.0, 0.0]), (1.0, &[2.0, 0.0, 0.0])],
+        );
+        light_track.easing = Easing::EaseIn;
+        light_track.repeat = Repeat::PingPong;
+        let animation = Animation::from_scene(&[light_track], 0).unwrap();
+        assert!(!animation.animates_camera());
+
+        let (mut instances, mut light, mut camera) = targets();
+        animation.apply(0.5, &mut instances, &mut light, &mut camera);
+        assert_eq!(light.position, [0.5, 0.0, 0.0]);
+        animation.apply(1.5, &mut instances, &mut light, &mut camera);
+        assert_eq!(light.position, [0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_bad_tracks() {
+        let bad = [
+            track(
+                Target::Light,
+                Property::Rotation,
+                &[(0.0, &[0.0, 0.0, 0.0, 1.0])],
+            ),
+            track(
+                Target::Instance(2),
+                Property::Scale,
+                &[(0.0, &[1.0, 1.0, 1.0])],
+            ),
+            track(Target::Camera, Property::Eye, &[(0.0, &[1.0, 1.0])]),
+            track(
+                Target::Camera,
+                Property::Fovy,
+                &[(1.0, &[45.0]), (0.0, &[90.0])],
+            ),
+            track(Target::Camera, Property::Fovy, &[]),
+        ];
+        for track in bad {
+            assert!(
+                Animation::from_scene(&[track.clone()], 2).is_err(),
+                "{:?}",
+                track
+            );
+        }
+    }
+
+    #[test]
+    fn clock_pauses_and_seeks() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.advance(0.0), 0.0);
+        assert_eq!(clock.advance(0.5), 0.5);
+
+        clock.set_playing(false);
+        assert_eq!(clock.advance(1.5), 0.5);
+        clock.set_playing(true);
+        assert_eq!(clock.advance(0.25), 0.75);
+
+        clock.seek(10.0);
+        assert_eq!(clock.advance(0.0), 10.0);
+    }
+
+    #[test]
+    fn default_scene_animates() {
+        let scene = Scene::from_json(include_str!("../../static/scene.json")).unwrap();
+        let animation =
+            Animation::from_scene(&scene.animations, scene.models[0].instances.len()).unwrap();
+        assert!(!animation.is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use super::{hdr, pipeline, shader, texture};
+
+/// What shows where the scene draws nothing.
+///
+/// Colours are linear and get tonemapped along with the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Background {
+    Solid([f32; 3]),
+    /// From the top of the canvas to the bottom.
+    Gradient([f32; 3], [f32; 3]),
+    /// Leaves the canvas clear so the page shows through it.
+    Transparent,
+    /// The environment's skybox, black when there is none.
+    Skybox,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Solid([0.0; 3])
+    }
+}
+
+impl Background {
+    pub(crate) fn clear_color(&self) -> wgpu::Color {
+        match *self {
+            Background::Solid([r, g, b]) => wgpu::Color {
+                r: r as f64,
+                g: g as f64,
+                b: b as f64,
+                a: 1.0,
+            },
+            Background::Transparent => wgpu::Color::TRANSPARENT,
+            Background::Gradient(..) | Background::Skybox => wgpu::Color::BLACK,
+        }
+    }
+}
+
+/// Backgrounds are drawn on the far plane after the scene, so they only
+/// fill the pixels nothing else covered.
+pub(crate) fn pipeline_state(scene: &pipeline::PipelineState) -> pipeline::PipelineState {
+    scene
+        .clone()
+        .cull_mode(None)
+        .depth(wgpu::CompareFunction::LessEqual, false)
+}
+
+/// Draws `Background::Gradient` with `gradient.wgsl`.
+#[derive(Debug)]
+pub(crate) struct GradientPass {
+    colors: [[f32; 4]; 2],
+    colors_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pub(crate) shader: shader::Shader,
+    pub(crate) pipeline_layout: Rc<wgpu::PipelineLayout>,
+    pub(crate) pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl GradientPass {
+    pub(crate) async fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut pipeline::PipelineCache,
+        scene_state: &pipeline::PipelineState,
+    ) -> Result<Self, anyhow::Error> {
+        let colors = [[0.0; 4]; 2];
+        let colors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Gradient Buffer"),
+            contents: bytemuck::cast_slice(&colors),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("gradient_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: colors_buffer.as_entire_binding(),
+            }],
+            label: Some("gradient_bind_group"),
+        });
+
+        let pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Gradient Pipeline Layout"),
+                bind_group_layouts: &[&layout],
+                push_constant_ranges: &[],
+            },
+        ));
+        let shader = shader::Shader::from_file_name("Gradient Shader", "gradient.wgsl").await?;
+        let pipeline = pipeline_cache.get_or_create(
+            device,
+            &shader,
+            &pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[],
+            &pipeline_state(scene_state),
+        )?;
+
+        Ok(Self {
+            colors,
+            colors_buffer,
+            bind_group,
+            shader,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    pub(crate) fn set_colors(&mut self, queue: &wgpu::Queue, top: [f32; 3], bottom: [f32; 3]) {
+        let colors = [top, bottom].map(|[r, g, b]| [r, g, b, 1.0]);
+        if self.colors != colors {
+            self.colors = colors;
+            queue.write_buffer(&self.colors_buffer, 0, bytemuck::cast_slice(&colors));
+        }
+    }
+
+    pub(crate) fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -1,4 +1,4 @@
-use cgmath::SquareMatrix;
+use cgmath::{InnerSpace, SquareMatrix};
 
 #[derive(Debug, Clone)]
 pub(crate) struct Camera {
@@ -30,11 +30,75 @@ impl Camera {
     }
 }
 
+/// Where the user moved the camera to, relative to where the scene has it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct View {
+    /// Radians around the target, sideways and then up.
+    pub(crate) orbit: (f32, f32),
+    /// Moves the target along the view's right and up, in distances to it.
+    pub(crate) pan: (f32, f32),
+    /// Divides the distance to the target.
+    pub(crate) zoom: f32,
+    /// Looks at this instead of the scene's target, from the same side.
+    pub(crate) focus: Option<[f32; 3]>,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            orbit: (0.0, 0.0),
+            pan: (0.0, 0.0),
+            zoom: 1.0,
+            focus: None,
+        }
+    }
+}
+
+impl View {
+    /// Keeps the camera from flipping over the top or bottom.
+    pub(crate) const MAX_PITCH: f32 = 1.5;
+
+    /// `camera` moved by this view.
+    pub(crate) fn apply(&self, camera: &Camera) -> Camera {
+        let up = camera.up.normalize();
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude() / self.zoom;
+        let direction = offset.normalize();
+
+        // Orbit in angles around `up`, so the pitch can be clamped
+        let flat = direction - up * direction.dot(up);
+        let flat = if flat.magnitude2() > 1e-12 {
+            flat.normalize()
+        } else {
+            up.cross(cgmath::Vector3::unit_x()).normalize()
+        };
+        let side = up.cross(flat);
+        let (sin_yaw, cos_yaw) = self.orbit.0.sin_cos();
+        let flat = flat * cos_yaw + side * sin_yaw;
+        let pitch = (direction.dot(up).clamp(-1.0, 1.0).asin() + self.orbit.1)
+            .clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+        let direction = flat * pitch.cos() + up * pitch.sin();
+
+        let right = up.cross(direction).normalize();
+        let view_up = direction.cross(right);
+        let target = self.focus.map_or(camera.target, cgmath::Point3::from)
+            + (right * self.pan.0 + view_up * self.pan.1) * distance;
+
+        Camera {
+            eye: target + direction * distance,
+            target,
+            ..camera.clone()
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub(crate) struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    /// Takes clip space back to world space, for the skybox.
+    inv_view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -42,12 +106,97 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     pub(crate) fn update_view_proj(&mut self, camera: &Camera) {
         // We're using Vector4 because of the uniforms 16 byte spacing requirement
         self.view_position = camera.eye.to_homogeneous().into();
-        self.view_proj = (OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix()).into();
+        let view_proj = OPENGL_TO_WGPU_MATRIX * camera.build_view_projection_matrix();
+        self.view_proj = view_proj.into();
+        self.inv_view_proj = view_proj
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity)
+            .into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera {
+            eye: (0.0, 0.0, 10.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+
+    fn assert_near(actual: cgmath::Point3<f32>, expected: [f32; 3]) {
+        assert!(
+            (actual - cgmath::Point3::from(expected)).magnitude() < 1e-4,
+            "{:?} isn't {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn default_view_keeps_the_camera() {
+        let camera = View::default().apply(&camera());
+        assert_near(camera.eye, [0.0, 0.0, 10.0]);
+        assert_near(camera.target, [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn orbits_around_the_target() {
+        let view = View {
+            orbit: (std::f32::consts::FRAC_PI_2, 0.0),
+            ..View::default()
+        };
+        assert_near(view.apply(&camera()).eye, [10.0, 0.0, 0.0]);
+
+        let view = View {
+            orbit: (0.0, 10.0),
+            ..View::default()
+        };
+        let eye = view.apply(&camera()).eye;
+        assert_near(
+            eye,
+            [
+                0.0,
+                10.0 * View::MAX_PITCH.sin(),
+                10.0 * View::MAX_PITCH.cos(),
+            ],
+        );
+    }
+
+    #[test]
+    fn zooms_and_pans_relative_to_the_distance() {
+        let view = View {
+            zoom: 2.0,
+            pan: (0.5, 0.0),
+            ..View::default()
+        };
+        let camera = view.apply(&camera());
+        assert_near(camera.target, [2.5, 0.0, 0.0]);
+        assert_near(camera.eye, [2.5, 0.0, 5.0]);
+    }
+
+    #[test]
+    fn focus_keeps_the_side() {
+        let view = View {
+            focus: Some([1.0, 2.0, 3.0]),
+            ..View::default()
+        };
+        let camera = view.apply(&camera());
+        assert_near(camera.target, [1.0, 2.0, 3.0]);
+        assert_near(camera.eye, [1.0, 2.0, 13.0]);
     }
 }
@@ -0,0 +1,306 @@
+use super::{background, camera, gizmo, hdr, outline, picking, post, scene, texture, Rander};
+
+/// Something for the renderer to do, see `State::handle`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    /// The canvas' backing store changed size, in pixels.
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    /// Move the camera away from where the scene puts it.
+    CameraInput {
+        view: camera::View,
+    },
+    SetLight(scene::SceneLight),
+    /// Add an instance of the model after the others.
+    SpawnInstance(scene::SceneInstance),
+    SetTonemapping {
+        tonemapping: hdr::Tonemapping,
+        exposure: f32,
+    },
+    SetBackground(background::Background),
+    SetPostEffect {
+        effect: post::Effect,
+        enabled: bool,
+    },
+    /// Grade colours with this LUT, see `Texture::lut_from_image`. `None`
+    /// goes back to the identity LUT, which grades nothing.
+    SetLut(Option<texture::TextureImage>),
+    SetOutline(outline::Outline),
+    /// Outline the instances at these indices, the gizmo goes on the first.
+    SetSelection(Vec<usize>),
+    /// `None` hides the gizmo.
+    SetGizmo(Option<gizmo::GizmoMode>),
+    /// Play or pause the animations and clips.
+    SetPlaying(bool),
+    /// Jump the animations and clips to this many seconds in.
+    Seek(f32),
+    /// Find what is drawn at a canvas pixel, answered with `Event::Picked`.
+    Pick {
+        pixel: (f32, f32),
+    },
+    /// Move the gizmo's handles with the pointer at a canvas pixel,
+    /// answered with `Event::GizmoDrag` when a drag starts or ends.
+    Pointer {
+        pixel: (f32, f32),
+        pressed: bool,
+    },
+    /// Answered with `Event::Saved`.
+    Save,
+}
+
+/// What the renderer answers a `Command` with.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Event {
+    /// `None` for the background.
+    Picked(Option<picking::Pick>),
+    /// Whether a gizmo handle is being dragged now.
+    GizmoDrag(bool),
+    /// The scene as it is now, as JSON.
+    Saved(String),
+}
+
+/// Add an instance to the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Spawn {
+    /// Bump this on every spawn, the same spawn only happens once.
+    pub(crate) generation: u32,
+    pub(crate) instance: scene::SceneInstance,
+}
+
+/// The commands that bring a renderer last given `previous` up to date
+/// with `props`, all of them when it wasn't given any yet. `to_canvas`
+/// takes CSS pixels to canvas pixels.
+pub(crate) fn from_props(
+    previous: Option<&Rander>,
+    props: &Rander,
+    to_canvas: impl Fn(i32, i32) -> (f32, f32),
+) -> Vec<Command> {
+    fn changed<T: PartialEq>(
+        previous: Option<&Rander>,
+        props: &Rander,
+        field: impl Fn(&Rander) -> T,
+    ) -> bool {
+        previous.map_or(true, |previous| field(previous) != field(props))
+    }
+
+    let mut commands = Vec::new();
+    if changed(previous, props, |props| props.spawn.clone()) {
+        if let Some(spawn) = &props.spawn {
+            commands.push(Command::SpawnInstance(spawn.instance.clone()));
+        }
+    }
+    if changed(previous, props, |props| props.view) {
+        commands.push(Command::CameraInput { view: props.view });
+    }
+    if changed(previous, props, |props| props.light.clone()) {
+        if let Some(light) = &props.light {
+            commands.push(Command::SetLight(light.clone()));
+        }
+    }
+    if changed(previous, props, |props| (props.tonemapping, props.exposure)) {
+        commands.push(Command::SetTonemapping {
+            tonemapping: props.tonemapping,
+            exposure: props.exposure,
+        });
+    }
+    if changed(previous, props, |props| props.background) {
+        commands.push(Command::SetBackground(props.background));
+    }
+    for effect in post::Effect::ALL {
+        let enabled = |props: &Rander| props.post_effects.contains(&effect);
+        if changed(previous, props, enabled) {
+            commands.push(Command::SetPostEffect {
+                effect,
+                enabled: enabled(props),
+            });
+        }
+    }
+    // Setting a LUT waits for its image to load, see `rander::load_lut`
+    if previous.map_or(false, |previous| previous.lut.is_some()) && props.lut.is_none() {
+        commands.push(Command::SetLut(None));
+    }
+    if changed(previous, props, |props| props.outline) {
+        commands.push(Command::SetOutline(props.outline));
+    }
+    if changed(previous, props, |props| props.selection.clone()) {
+        commands.push(Command::SetSelection(props.selection.clone()));
+    }
+    if changed(previous, props, |props| props.gizmo) {
+        commands.push(Command::SetGizmo(props.gizmo));
+    }
+    if changed(previous, props, |props| props.playing) {
+        commands.push(Command::SetPlaying(props.playing));
+    }
+    if changed(previous, props, |props| props.seek) {
+        if let Some(seek) = props.seek {
+            commands.push(Command::Seek(seek.time));
+        }
+    }
+    if changed(previous, props, |props| props.click) {
+        if let Some(click) = props.click {
+            commands.push(Command::Pick {
+                pixel: to_canvas(click.x, click.y),
+            });
+        }
+    }
+    if changed(previous, props, |props| props.pointer) {
+        if let Some(pointer) = props.pointer {
+            commands.push(Command::Pointer {
+                pixel: to_canvas(pointer.x, pointer.y),
+                pressed: pointer.pressed,
+            });
+        }
+    }
+    // Saving the scene is the one thing a renderer that was never given
+    // any props shouldn't do straight away
+    if previous.map_or(0, |previous| previous.save_generation) != props.save_generation {
+        commands.push(Command::Save);
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use yew::Callback;
+
+    use super::*;
+    use crate::rander::animation;
+
+    fn props() -> Rander {
+        Rander {
+            view: camera::View::default(),
+            shader_generation: 0,
+            on_shader_error: Callback::noop(),
+            sample_count: 4,
+            max_pixel_ratio: 2.0,
+            scene: "scene.json".to_string(),
+            gpu_culling: true,
+            exposure: 1.0,
+            tonemapping: hdr::Tonemapping::Aces,
+            background: background::Background::Skybox,
+            post_effects: vec![post::Effect::Bloom],
+            click: None,
+            on_pick: Callback::noop(),
+            selection: Vec::new(),
+            outline: outline::Outline::default(),
+            gizmo: None,
+            pointer: None,
+            on_gizmo_drag: Callback::noop(),
+            save_generation: 0,
+            on_save: Callback::noop(),
+            playing: true,
+            seek: None,
+            light: None,
+            spawn: None,
+            lut: None,
+        }
+    }
+
+    fn to_canvas(x: i32, y: i32) -> (f32, f32) {
+        (x as f32 * 2.0, y as f32 * 2.0)
+    }
+
+    #[test]
+    fn sends_everything_at_first() {
+        let commands = from_props(None, &props(), to_canvas);
+        assert!(commands.contains(&Command::CameraInput {
+            view: camera::View::default()
+        }));
+        assert!(commands.contains(&Command::SetBackground(background::Background::Skybox)));
+        assert!(commands.contains(&Command::SetPostEffect {
+            effect: post::Effect::Bloom,
+            enabled: true,
+        }));
+        assert!(commands.contains(&Command::SetPostEffect {
+            effect: post::Effect::Fxaa,
+            enabled: false,
+        }));
+        assert!(commands.contains(&Command::SetPlaying(true)));
+        assert!(!commands.contains(&Command::Save));
+    }
+
+    #[test]
+    fn sends_only_what_changed() {
+        let previous = props();
+        assert_eq!(
+            from_props(Some(&previous), &previous, to_canvas),
+            Vec::new()
+        );
+
+        let view = camera::View {
+            zoom: 2.0,
+            ..camera::View::default()
+        };
+        let props = Rander {
+            view,
+            post_effects: vec![post::Effect::Bloom, post::Effect::Fxaa],
+            ..previous.clone()
+        };
+        assert_eq!(
+            from_props(Some(&previous), &props, to_canvas),
+            vec![
+                Command::CameraInput { view },
+                Command::SetPostEffect {
+                    effect: post::Effect::Fxaa,
+                    enabled: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn one_off_commands_happen_once() {
+        let previous = props();
+        let props = Rander {
+            click: Some(picking::Click {
+                generation: 1,
+                x: 10,
+                y: 20,
+            }),
+            seek: Some(animation::Seek {
+                generation: 1,
+                time: 2.5,
+            }),
+            spawn: Some(Spawn {
+                generation: 1,
+                instance: scene::SceneInstance {
+                    position: [1.0, 2.0, 3.0],
+                    rotation: [0.0, 0.0, 0.0, 1.0],
+                    scale: [1.0, 1.0, 1.0],
+                },
+            }),
+            save_generation: 1,
+            ..previous.clone()
+        };
+
+        let commands = from_props(Some(&previous), &props, to_canvas);
+        assert_eq!(
+            commands,
+            vec![
+                Command::SpawnInstance(props.spawn.clone().unwrap().instance),
+                Command::Seek(2.5),
+                Command::Pick {
+                    pixel: (20.0, 40.0)
+                },
+                Command::Save,
+            ]
+        );
+        assert_eq!(from_props(Some(&props), &props, to_canvas), Vec::new());
+    }
+
+    #[test]
+    fn unsetting_the_lut_resets_it() {
+        let previous = Rander {
+            lut: Some("lut-warm.png".to_string()),
+            ..props()
+        };
+        assert_eq!(
+            from_props(Some(&previous), &props(), to_canvas),
+            vec![Command::SetLut(None)]
+        );
+        assert_eq!(from_props(Some(&props()), &previous, to_canvas), Vec::new());
+        assert!(!from_props(None, &props(), to_canvas).contains(&Command::SetLut(None)));
+    }
+}
@@ -0,0 +1,200 @@
+use cgmath::{ElementWise, InnerSpace, Matrix, Vector3, Vector4};
+
+use super::instance::Instance;
+
+/// Axis-aligned bounding box in model space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Aabb {
+    pub(crate) min: Vector3<f32>,
+    pub(crate) max: Vector3<f32>,
+}
+
+impl Aabb {
+    /// `None` when there are no points.
+    pub(crate) fn from_points(points: impl IntoIterator<Item = Vector3<f32>>) -> Option<Self> {
+        points.into_iter().fold(None, |aabb, p| {
+            Some(match aabb {
+                Some(aabb) => Aabb {
+                    min: min(aabb.min, p),
+                    max: max(aabb.max, p),
+                },
+                None => Aabb { min: p, max: p },
+            })
+        })
+    }
+
+    pub(crate) fn union(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: min(self.min, other.min),
+            max: max(self.max, other.max),
+        }
+    }
+
+    pub(crate) fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    pub(crate) fn bounding_sphere(&self) -> Sphere {
+        Sphere {
+            center: self.center(),
+            radius: (self.max - self.min).magnitude() / 2.0,
+        }
+    }
+}
+
+fn min(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn max(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Sphere {
+    pub(crate) center: Vector3<f32>,
+    pub(crate) radius: f32,
+}
+
+impl Sphere {
+    /// A sphere around the model placed by `instance`, grown by its
+    /// largest scale.
+    pub(crate) fn transformed(&self, instance: &Instance) -> Sphere {
+        let scale = instance.scale.map(f32::abs);
+        Sphere {
+            center: instance.position
+                + instance.rotation * self.center.mul_element_wise(instance.scale),
+            radius: self.radius * scale.x.max(scale.y).max(scale.z),
+        }
+    }
+}
+
+/// The six planes of a view frustum, normals pointing inwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Planes of a view-projection matrix with wgpu's 0 to 1 depth range,
+    /// like `Camera::build_view_projection_matrix`.
+    pub(crate) fn from_view_projection(m: cgmath::Matrix4<f32>) -> Self {
+        let (x, y, z, w) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z]
+            .map(|plane| plane / plane.truncate().magnitude());
+
+        Self { planes }
+    }
+
+    pub(crate) fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(Into::into)
+    }
+
+    pub(crate) fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+}
+
+/// Indices of the instances whose copy of a model with `bounds` is at
+/// least partly inside `frustum`.
+pub(crate) fn visible_instances<'a>(
+    instances: &'a [Instance],
+    bounds: &'a Sphere,
+    frustum: &'a Frustum,
+) -> impl Iterator<Item = usize> + 'a {
+    instances
+        .iter()
+        .enumerate()
+        .filter(move |(_, instance)| frustum.intersects_sphere(&bounds.transformed(instance)))
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Quaternion, Rotation3};
+
+    use super::*;
+    use crate::rander::camera::Camera;
+
+    fn frustum() -> Frustum {
+        let camera = Camera {
+            eye: (0.0, 0.0, 10.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        Frustum::from_view_projection(camera.build_view_projection_matrix())
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> Sphere {
+        Sphere {
+            center: Vector3::new(x, y, z),
+            radius,
+        }
+    }
+
+    fn instance(x: f32, y: f32, z: f32) -> Instance {
+        Instance {
+            position: Vector3::new(x, y, z),
+            rotation: Quaternion::from_angle_y(Deg(0.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn spheres_against_the_frustum() {
+        let frustum = frustum();
+        // In front of the camera
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 0.0, 0.5)));
+        // Behind it
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 20.0, 1.0)));
+        // The right plane is about 4.14 out at this depth
+        assert!(frustum.intersects_sphere(&sphere(4.5, 0.0, 0.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(6.0, 0.0, 0.0, 1.0)));
+        // Beyond `zfar`, and straddling it
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -100.0, 1.0)));
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -90.5, 1.0)));
+    }
+
+    #[test]
+    fn aabb_of_points() {
+        assert_eq!(Aabb::from_points(std::iter::empty()), None);
+
+        let aabb = Aabb::from_points([Vector3::new(1.0, -2.0, 0.0), Vector3::new(-1.0, 3.0, 0.5)])
+            .unwrap();
+        assert_eq!(aabb.min, Vector3::new(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, Vector3::new(1.0, 3.0, 0.5));
+    }
+
+    #[test]
+    fn spheres_follow_non_uniform_scale() {
+        let instance = Instance {
+            position: Vector3::new(0.0, 2.0, 0.0),
+            rotation: Quaternion::from_angle_z(Deg(90.0)),
+            scale: Vector3::new(2.0, -3.0, 1.0),
+        };
+        let sphere = sphere(1.0, 0.0, 0.0, 1.0).transformed(&instance);
+        assert!((sphere.center - Vector3::new(0.0, 4.0, 0.0)).magnitude() < 1e-5);
+        assert_eq!(sphere.radius, 3.0);
+    }
+
+    #[test]
+    fn only_visible_instances() {
+        let instances = [
+            instance(0.0, 0.0, 0.0),
+            instance(0.0, 0.0, 20.0),
+            instance(50.0, 0.0, 0.0),
+            instance(2.0, 1.0, -5.0),
+        ];
+        let bounds = sphere(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(
+            visible_instances(&instances, &bounds, &frustum()).collect::<Vec<_>>(),
+            vec![0, 3]
+        );
+    }
+}
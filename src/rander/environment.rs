@@ -0,0 +1,356 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use super::{background, hdr, pipeline, shader, texture};
+
+const CUBE_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTER_SIZE: u32 = 128;
+/// Roughness goes from 0 at the top level to 1 at the last one.
+/// `shader.wgsl` samples up to level `PREFILTER_MIPS - 1`.
+pub(crate) const PREFILTER_MIPS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+
+/// A skybox and the maps `shader.wgsl` needs for image-based lighting,
+/// baked from an equirectangular HDR image when loaded.
+///
+/// The textures live on in the bind groups that use them.
+#[derive(Debug)]
+pub(crate) struct Environment {
+    /// `@group(3)` of `shader.wgsl` when built with `IBL`.
+    pub(crate) ibl_layout: wgpu::BindGroupLayout,
+    pub(crate) ibl_bind_group: wgpu::BindGroup,
+    skybox_bind_group: wgpu::BindGroup,
+    pub(crate) skybox_shader: shader::Shader,
+    pub(crate) skybox_pipeline_layout: Rc<wgpu::PipelineLayout>,
+    pub(crate) skybox_pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl Environment {
+    pub(crate) async fn from_file_name(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipeline_cache: &mut pipeline::PipelineCache,
+        camera_layout: &wgpu::BindGroupLayout,
+        pipeline_state: &pipeline::PipelineState,
+        file_name: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let image = texture::TextureImage::from_file_name(file_name).await?;
+        let equirect = texture::Texture::from_hdr_image(device, queue, image, file_name);
+
+        let cube = texture::Texture::create_cube_map(device, CUBE_SIZE, 1, "environment_cube");
+        let irradiance =
+            texture::Texture::create_cube_map(device, IRRADIANCE_SIZE, 1, "irradiance_cube");
+        let prefiltered = texture::Texture::create_cube_map(
+            device,
+            PREFILTER_SIZE,
+            PREFILTER_MIPS,
+            "prefiltered_cube",
+        );
+        let brdf_lut =
+            texture::Texture::create_baked_target(device, BRDF_LUT_SIZE, hdr::FORMAT, "brdf_lut");
+
+        let mut bake = Bake::new(device);
+        let equirect_layout = source_layout(device, wgpu::TextureViewDimension::D2, false);
+        let cube_layout = source_layout(device, wgpu::TextureViewDimension::Cube, true);
+        let equirect_bind_group = source_bind_group(device, &equirect_layout, &equirect);
+        let cube_bind_group = source_bind_group(device, &cube_layout, &cube);
+
+        let equirect_pass = bake
+            .pipeline("environment/equirect.wgsl", &equirect_layout)
+            .await?;
+        for face in 0..6 {
+            bake.draw(
+                &equirect_pass,
+                &equirect_bind_group,
+                &cube.face_view(face, 0),
+                [face as f32, 0.0, 0.0, 0.0],
+            );
+        }
+
+        let irradiance_pass = bake
+            .pipeline("environment/irradiance.wgsl", &cube_layout)
+            .await?;
+        for face in 0..6 {
+            bake.draw(
+                &irradiance_pass,
+                &cube_bind_group,
+                &irradiance.face_view(face, 0),
+                [face as f32, 0.0, 0.0, 0.0],
+            );
+        }
+
+        let prefilter_pass = bake
+            .pipeline("environment/prefilter.wgsl", &cube_layout)
+            .await?;
+        for mip in 0..PREFILTER_MIPS {
+            let roughness = mip as f32 / (PREFILTER_MIPS - 1) as f32;
+            for face in 0..6 {
+                bake.draw(
+                    &prefilter_pass,
+                    &cube_bind_group,
+                    &prefiltered.face_view(face, mip),
+                    [face as f32, roughness, 0.0, 0.0],
+                );
+            }
+        }
+
+        let brdf_pass = bake.pipeline("environment/brdf.wgsl", &cube_layout).await?;
+        bake.draw(&brdf_pass, &cube_bind_group, &brdf_lut.view, [0.0; 4]);
+
+        queue.submit(std::iter::once(bake.encoder.finish()));
+
+        let ibl_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::Cube, true),
+                texture_entry(1, wgpu::TextureViewDimension::Cube, true),
+                texture_entry(2, wgpu::TextureViewDimension::D2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("ibl_bind_group_layout"),
+        });
+        let ibl_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let ibl_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &ibl_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&irradiance.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&prefiltered.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&brdf_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&ibl_sampler),
+                },
+            ],
+            label: Some("ibl_bind_group"),
+        });
+
+        let skybox_layout = source_layout(device, wgpu::TextureViewDimension::Cube, true);
+        let skybox_bind_group = source_bind_group(device, &skybox_layout, &cube);
+        let skybox_pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Skybox Pipeline Layout"),
+                bind_group_layouts: &[camera_layout, &skybox_layout],
+                push_constant_ranges: &[],
+            },
+        ));
+        let skybox_shader = shader::Shader::from_file_name("Skybox Shader", "skybox.wgsl").await?;
+        let skybox_pipeline = pipeline_cache.get_or_create(
+            device,
+            &skybox_shader,
+            &skybox_pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[],
+            &background::pipeline_state(pipeline_state),
+        )?;
+
+        Ok(Self {
+            ibl_layout,
+            ibl_bind_group,
+            skybox_bind_group,
+            skybox_shader,
+            skybox_pipeline_layout,
+            skybox_pipeline,
+        })
+    }
+
+    pub(crate) fn draw_skybox<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.skybox_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.skybox_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Fullscreen passes that render the environment maps once.
+///
+/// Each shader reads its source from `@group(0)` and a `vec4<f32>` of
+/// parameters from `@group(1)`, x is always the cube face being drawn.
+struct Bake<'a> {
+    device: &'a wgpu::Device,
+    encoder: wgpu::CommandEncoder,
+    params_layout: wgpu::BindGroupLayout,
+}
+
+impl<'a> Bake<'a> {
+    fn new(device: &'a wgpu::Device) -> Self {
+        let encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("bake_params_bind_group_layout"),
+        });
+
+        Self {
+            device,
+            encoder,
+            params_layout,
+        }
+    }
+
+    /// These only run once, so they skip the pipeline cache.
+    async fn pipeline(
+        &self,
+        filename: &str,
+        source_layout: &wgpu::BindGroupLayout,
+    ) -> Result<wgpu::RenderPipeline, anyhow::Error> {
+        let layout = self
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(filename),
+                bind_group_layouts: &[source_layout, &self.params_layout],
+                push_constant_ranges: &[],
+            });
+        let shader = shader::Shader::from_file_name(filename, filename).await?;
+
+        Ok(shader.create_render_pipeline(
+            self.device,
+            &layout,
+            hdr::FORMAT,
+            None,
+            &[],
+            &pipeline::PipelineState::default().cull_mode(None),
+        )?)
+    }
+
+    fn draw(
+        &mut self,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        params: [f32; 4],
+    ) {
+        // Every draw needs its own parameters, they're all submitted at once
+        let params_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("bake_params"),
+                contents: bytemuck::cast_slice(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let params_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+            label: Some("bake_params_bind_group"),
+        });
+
+        let mut pass = self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Bake Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, source, &[]);
+        pass.set_bind_group(1, &params_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn texture_entry(
+    binding: u32,
+    view_dimension: wgpu::TextureViewDimension,
+    filterable: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension,
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+        },
+        count: None,
+    }
+}
+
+/// A texture and its sampler at bindings 0 and 1.
+fn source_layout(
+    device: &wgpu::Device,
+    view_dimension: wgpu::TextureViewDimension,
+    filterable: bool,
+) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            texture_entry(0, view_dimension, filterable),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(if filterable {
+                    wgpu::SamplerBindingType::Filtering
+                } else {
+                    wgpu::SamplerBindingType::NonFiltering
+                }),
+                count: None,
+            },
+        ],
+        label: Some("environment_source_bind_group_layout"),
+    })
+}
+
+fn source_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    source: &texture::Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&source.sampler),
+            },
+        ],
+        label: Some("environment_source_bind_group"),
+    })
+}
@@ -0,0 +1,154 @@
+use std::{cell::RefCell, rc::Rc};
+
+use gloo::{
+    events::EventListener,
+    render::{request_animation_frame, AnimationFrame},
+};
+
+/// Seconds between animation frames.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct FrameTimer {
+    /// Timestamp of the last frame, in milliseconds.
+    last: Option<f64>,
+}
+
+impl FrameTimer {
+    /// Longest step handed out, so a stalled frame doesn't make everything
+    /// jump ahead.
+    pub(crate) const MAX_DELTA: f32 = 0.1;
+
+    /// Seconds since the last `tick`, 0 for the first one.
+    pub(crate) fn tick(&mut self, now: f64) -> f32 {
+        let delta = self.last.map_or(0.0, |last| ((now - last) / 1000.0) as f32);
+        self.last = Some(now);
+        delta.clamp(0.0, Self::MAX_DELTA)
+    }
+
+    /// Start over, so the time in between isn't counted.
+    pub(crate) fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+struct Inner {
+    timer: FrameTimer,
+    on_frame: Box<dyn FnMut(f32) -> bool>,
+    /// The next frame, or the one running now.
+    frame: Option<AnimationFrame>,
+    pending: bool,
+    stopped: bool,
+}
+
+/// Calls `on_frame` with the seconds since the previous frame on every
+/// `requestAnimationFrame` while the page is visible. It stops for good
+/// once `on_frame` returns `false` or the loop is dropped.
+pub(crate) struct FrameLoop {
+    _inner: Rc<RefCell<Inner>>,
+    _visibility: EventListener,
+}
+
+impl FrameLoop {
+    pub(crate) fn new(on_frame: impl FnMut(f32) -> bool + 'static) -> Self {
+        let inner = Rc::new(RefCell::new(Inner {
+            timer: FrameTimer::default(),
+            on_frame: Box::new(on_frame),
+            frame: None,
+            pending: false,
+            stopped: false,
+        }));
+
+        let visibility = {
+            let inner = Rc::downgrade(&inner);
+            EventListener::new(&gloo::utils::document(), "visibilitychange", move |_| {
+                let inner = match inner.upgrade() {
+                    Some(inner) => inner,
+                    None => return,
+                };
+                if hidden() {
+                    let mut inner = inner.borrow_mut();
+                    inner.frame = None;
+                    inner.pending = false;
+                    inner.timer.reset();
+                } else {
+                    let resume = {
+                        let inner = inner.borrow();
+                        !inner.stopped && !inner.pending
+                    };
+                    if resume {
+                        schedule(&inner);
+                    }
+                }
+            })
+        };
+
+        if !hidden() {
+            schedule(&inner);
+        }
+        Self {
+            _inner: inner,
+            _visibility: visibility,
+        }
+    }
+}
+
+fn schedule(inner: &Rc<RefCell<Inner>>) {
+    let weak = Rc::downgrade(inner);
+    let frame = request_animation_frame(move |now| {
+        if let Some(inner) = weak.upgrade() {
+            run_frame(&inner, now);
+        }
+    });
+
+    let mut inner = inner.borrow_mut();
+    inner.frame = Some(frame);
+    inner.pending = true;
+}
+
+fn run_frame(inner: &Rc<RefCell<Inner>>, now: f64) {
+    let keep_going = {
+        let mut inner = inner.borrow_mut();
+        inner.pending = false;
+        let delta = inner.timer.tick(now);
+        (inner.on_frame)(delta)
+    };
+
+    if !keep_going {
+        inner.borrow_mut().stopped = true;
+    } else if !hidden() {
+        schedule(inner);
+    }
+}
+
+fn hidden() -> bool {
+    gloo::utils::document().hidden()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_frame_has_no_delta() {
+        let mut timer = FrameTimer::default();
+        assert_eq!(timer.tick(1000.0), 0.0);
+        assert_eq!(timer.tick(1016.0), 0.016);
+        assert_eq!(timer.tick(1048.0), 0.032);
+    }
+
+    #[test]
+    fn long_gaps_are_capped() {
+        let mut timer = FrameTimer::default();
+        timer.tick(0.0);
+        assert_eq!(timer.tick(5000.0), FrameTimer::MAX_DELTA);
+        assert_eq!(timer.tick(4000.0), 0.0);
+    }
+
+    #[test]
+    fn reset_skips_the_gap() {
+        let mut timer = FrameTimer::default();
+        timer.tick(0.0);
+        timer.reset();
+        assert_eq!(timer.tick(60_000.0), 0.0);
+        assert_eq!(timer.tick(60_010.0), 0.01);
+    }
+}
@@ -0,0 +1,323 @@
+use super::camera::View;
+
+/// How far a pointer can move, in CSS pixels, and still be tapping.
+const TAP_SLOP: f32 = 10.0;
+/// Longest press that still counts as a tap, in milliseconds.
+const TAP_TIME: f64 = 300.0;
+/// Longest wait between the taps of a double tap, in milliseconds.
+const DOUBLE_TAP_TIME: f64 = 300.0;
+/// How far apart the taps of a double tap can be, in CSS pixels.
+const DOUBLE_TAP_SLOP: f32 = 30.0;
+
+/// Radians the camera orbits per CSS pixel dragged.
+const ORBIT_SPEED: f32 = 0.01;
+/// Distances to the target the camera pans per CSS pixel dragged.
+const PAN_SPEED: f32 = 0.002;
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 10.0;
+
+/// What the pointers on the canvas are doing, positions in CSS pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Gesture {
+    /// One pointer dragged this far.
+    Orbit {
+        delta: (f32, f32),
+    },
+    /// Two pointers moved their middle this far and spread out by `zoom`
+    /// times.
+    PanZoom {
+        pan: (f32, f32),
+        zoom: f32,
+    },
+    Tap {
+        position: (f32, f32),
+    },
+    /// The second tap of a double tap, after the `Tap` for the first one.
+    DoubleTap {
+        position: (f32, f32),
+    },
+}
+
+impl Gesture {
+    /// `view` moved the way this gesture moves the camera. Taps don't.
+    pub(crate) fn move_view(&self, view: View) -> View {
+        match *self {
+            Gesture::Orbit { delta } => View {
+                orbit: (
+                    view.orbit.0 - delta.0 * ORBIT_SPEED,
+                    view.orbit.1 + delta.1 * ORBIT_SPEED,
+                ),
+                ..view
+            },
+            Gesture::PanZoom { pan, zoom } => View {
+                // Keep what is under the fingers under them
+                pan: (
+                    view.pan.0 - pan.0 * PAN_SPEED / view.zoom,
+                    view.pan.1 + pan.1 * PAN_SPEED / view.zoom,
+                ),
+                zoom: (view.zoom * zoom).clamp(MIN_ZOOM, MAX_ZOOM),
+                ..view
+            },
+            Gesture::Tap { .. } | Gesture::DoubleTap { .. } => view,
+        }
+    }
+}
+
+/// A pointer that went down and might still be a tap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Press {
+    position: (f32, f32),
+    time: f64,
+}
+
+/// Turns pointer events into `Gesture`s. Only the first two pointers down
+/// count, the rest are ignored until they're lifted.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Gestures {
+    /// Pointer ids and where they are now.
+    pointers: Vec<(i32, (f32, f32))>,
+    /// The press of a lone pointer, until it moves too far or another joins.
+    press: Option<Press>,
+    /// The last tap, until it turns into a double tap or gets too old.
+    last_tap: Option<Press>,
+    /// A second pointer went down since all were last up. The one left
+    /// after a pinch doesn't orbit, or lifting fingers one by one would
+    /// jerk the camera around.
+    pinched: bool,
+}
+
+impl Gestures {
+    pub(crate) fn down(&mut self, id: i32, position: (f32, f32), time: f64) {
+        if self.pointers.len() >= 2 || self.index(id).is_some() {
+            return;
+        }
+        self.pointers.push((id, position));
+
+        if self.pointers.len() == 1 {
+            self.press = Some(Press { position, time });
+        } else {
+            self.press = None;
+            self.pinched = true;
+        }
+    }
+
+    pub(crate) fn moved(&mut self, id: i32, position: (f32, f32)) -> Option<Gesture> {
+        let index = self.index(id)?;
+        let before = self.pointers.clone();
+        self.pointers[index].1 = position;
+
+        match self.pointers.len() {
+            1 if self.pinched => None,
+            1 => {
+                let from = match self.press {
+                    // Not dragging until it leaves the tap slop, then from
+                    // where it went down
+                    Some(press) if distance(press.position, position) <= TAP_SLOP => return None,
+                    Some(press) => press.position,
+                    None => before[index].1,
+                };
+                self.press = None;
+                Some(Gesture::Orbit {
+                    delta: (position.0 - from.0, position.1 - from.1),
+                })
+            }
+            _ => {
+                let (middle_before, spread_before) = middle_and_spread(&before);
+                let (middle, spread) = middle_and_spread(&self.pointers);
+                Some(Gesture::PanZoom {
+                    pan: (middle.0 - middle_before.0, middle.1 - middle_before.1),
+                    zoom: if spread_before > 1.0 {
+                        spread / spread_before
+                    } else {
+                        1.0
+                    },
+                })
+            }
+        }
+    }
+
+    pub(crate) fn up(&mut self, id: i32, position: (f32, f32), time: f64) -> Option<Gesture> {
+        let index = self.index(id)?;
+        self.pointers.remove(index);
+        if !self.pointers.is_empty() {
+            return None;
+        }
+        self.pinched = false;
+
+        let press = self.press.take()?;
+        if time - press.time > TAP_TIME || distance(press.position, position) > TAP_SLOP {
+            return None;
+        }
+
+        match self.last_tap.take() {
+            Some(last)
+                if press.time - last.time <= DOUBLE_TAP_TIME
+                    && distance(last.position, position) <= DOUBLE_TAP_SLOP =>
+            {
+                Some(Gesture::DoubleTap { position })
+            }
+            _ => {
+                self.last_tap = Some(Press { position, time });
+                Some(Gesture::Tap { position })
+            }
+        }
+    }
+
+    /// The browser took the pointer over, for scrolling say.
+    pub(crate) fn cancel(&mut self, id: i32) {
+        if let Some(index) = self.index(id) {
+            self.pointers.remove(index);
+        }
+        self.press = None;
+        if self.pointers.is_empty() {
+            self.pinched = false;
+        }
+    }
+
+    fn index(&self, id: i32) -> Option<usize> {
+        self.pointers.iter().position(|&(other, _)| other == id)
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// Middle of the first two pointers and how far apart they are.
+fn middle_and_spread(pointers: &[(i32, (f32, f32))]) -> ((f32, f32), f32) {
+    let (a, b) = (pointers[0].1, pointers[1].1);
+    (((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0), distance(a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_pointer_orbits_once_past_the_slop() {
+        let mut gestures = Gestures::default();
+        gestures.down(1, (100.0, 100.0), 0.0);
+        assert_eq!(gestures.moved(1, (105.0, 100.0)), None);
+        assert_eq!(
+            gestures.moved(1, (120.0, 100.0)),
+            Some(Gesture::Orbit { delta: (20.0, 0.0) })
+        );
+        assert_eq!(
+            gestures.moved(1, (125.0, 90.0)),
+            Some(Gesture::Orbit {
+                delta: (5.0, -10.0)
+            })
+        );
+        assert_eq!(gestures.up(1, (125.0, 90.0), 100.0), None);
+    }
+
+    #[test]
+    fn two_pointers_pan_and_zoom() {
+        let mut gestures = Gestures::default();
+        gestures.down(1, (100.0, 100.0), 0.0);
+        gestures.down(2, (200.0, 100.0), 10.0);
+        assert_eq!(
+            gestures.moved(2, (300.0, 100.0)),
+            Some(Gesture::PanZoom {
+                pan: (50.0, 0.0),
+                zoom: 2.0,
+            })
+        );
+        assert_eq!(
+            gestures.moved(1, (100.0, 120.0)),
+            Some(Gesture::PanZoom {
+                pan: (0.0, 10.0),
+                zoom: (200.0f32.hypot(20.0)) / 200.0,
+            })
+        );
+
+        // Lifting one doesn't start orbiting or tap
+        assert_eq!(gestures.up(2, (300.0, 100.0), 50.0), None);
+        assert_eq!(gestures.moved(1, (200.0, 200.0)), None);
+        assert_eq!(gestures.up(1, (200.0, 200.0), 60.0), None);
+
+        gestures.down(1, (0.0, 0.0), 1000.0);
+        assert_eq!(
+            gestures.moved(1, (0.0, 50.0)),
+            Some(Gesture::Orbit { delta: (0.0, 50.0) })
+        );
+    }
+
+    #[test]
+    fn quick_presses_tap_and_double_tap() {
+        let mut gestures = Gestures::default();
+        gestures.down(1, (50.0, 50.0), 0.0);
+        assert_eq!(
+            gestures.up(1, (52.0, 50.0), 100.0),
+            Some(Gesture::Tap {
+                position: (52.0, 50.0)
+            })
+        );
+        gestures.down(2, (60.0, 55.0), 250.0);
+        assert_eq!(
+            gestures.up(2, (60.0, 55.0), 300.0),
+            Some(Gesture::DoubleTap {
+                position: (60.0, 55.0)
+            })
+        );
+        // A third tap starts over
+        gestures.down(3, (60.0, 55.0), 400.0);
+        assert_eq!(
+            gestures.up(3, (60.0, 55.0), 450.0),
+            Some(Gesture::Tap {
+                position: (60.0, 55.0)
+            })
+        );
+    }
+
+    #[test]
+    fn slow_or_far_taps_are_not_double() {
+        let mut gestures = Gestures::default();
+        gestures.down(1, (50.0, 50.0), 0.0);
+        gestures.up(1, (50.0, 50.0), 50.0);
+        gestures.down(1, (50.0, 50.0), 1000.0);
+        assert_eq!(
+            gestures.up(1, (50.0, 50.0), 1050.0),
+            Some(Gesture::Tap {
+                position: (50.0, 50.0)
+            })
+        );
+        gestures.down(1, (200.0, 50.0), 1100.0);
+        assert_eq!(
+            gestures.up(1, (200.0, 50.0), 1150.0),
+            Some(Gesture::Tap {
+                position: (200.0, 50.0)
+            })
+        );
+
+        // Held too long
+        gestures.down(1, (200.0, 50.0), 2000.0);
+        assert_eq!(gestures.up(1, (200.0, 50.0), 2500.0), None);
+    }
+
+    #[test]
+    fn cancelled_pointers_are_forgotten() {
+        let mut gestures = Gestures::default();
+        gestures.down(1, (50.0, 50.0), 0.0);
+        gestures.cancel(1);
+        assert_eq!(gestures.up(1, (50.0, 50.0), 10.0), None);
+        assert_eq!(gestures.moved(1, (80.0, 50.0)), None);
+    }
+
+    #[test]
+    fn gestures_move_the_view() {
+        let view = Gesture::Orbit {
+            delta: (10.0, 20.0),
+        }
+        .move_view(View::default());
+        assert_eq!(view.orbit, (-10.0 * ORBIT_SPEED, 20.0 * ORBIT_SPEED));
+
+        let view = Gesture::PanZoom {
+            pan: (0.0, 0.0),
+            zoom: 100.0,
+        }
+        .move_view(view);
+        assert_eq!(view.zoom, MAX_ZOOM);
+        assert_eq!(view.orbit, (-10.0 * ORBIT_SPEED, 20.0 * ORBIT_SPEED));
+    }
+}
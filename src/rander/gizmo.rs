@@ -0,0 +1,565 @@
+use std::rc::Rc;
+
+use cgmath::{InnerSpace, MetricSpace, Rotation3, Vector3};
+
+use super::{camera::Camera, hdr, instance::Instance, picking::Ray, pipeline, shader, texture};
+
+/// What dragging the gizmo's handles does to the selected instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GizmoMode {
+    /// Arrows along the world axes, squares for the planes between them.
+    Translate,
+    /// Rings around the world axes.
+    Rotate,
+    /// Boxes along the instance's own axes, the one in the middle scales
+    /// all of them.
+    Scale,
+}
+
+/// The pointer over the canvas, in CSS pixels from its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Pointer {
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) pressed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Handle {
+    /// Along one axis, translate or scale.
+    Axis(usize),
+    /// In the plane across one axis.
+    Plane(usize),
+    /// Around one axis.
+    Ring(usize),
+    /// Every axis at once.
+    Center,
+}
+
+/// Length of the axes, as a fraction of the viewport height.
+const SCREEN_SIZE: f32 = 0.15;
+/// How close to a handle the pointer has to be, relative to the axes.
+const PICK_WIDTH: f32 = 0.08;
+/// Where the plane squares start and end along their two axes.
+const PLANE: (f32, f32) = (0.25, 0.45);
+const MIN_SCALE: f32 = 0.01;
+
+const COLORS: [[f32; 4]; 3] = [
+    [1.0, 0.1, 0.1, 1.0],
+    [0.1, 1.0, 0.1, 1.0],
+    [0.2, 0.3, 1.0, 1.0],
+];
+const CENTER_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const ACTIVE_COLOR: [f32; 4] = [1.0, 0.9, 0.1, 1.0];
+
+/// Where the gizmo is drawn for an instance, seen from a camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Frame {
+    center: Vector3<f32>,
+    axes: [Vector3<f32>; 3],
+    /// Length of the axes, so the gizmo keeps its size on screen.
+    size: f32,
+    /// Towards the camera.
+    view: Vector3<f32>,
+}
+
+impl Frame {
+    fn new(mode: GizmoMode, instance: &Instance, camera: &Camera) -> Self {
+        use cgmath::EuclideanSpace;
+
+        let axes = match mode {
+            GizmoMode::Scale => [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+                .map(|axis| instance.rotation * axis),
+            _ => [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()],
+        };
+        let eye = camera.eye.to_vec();
+        let distance = eye.distance(instance.position).max(camera.znear);
+        let tan_half_fovy = (cgmath::Rad::from(cgmath::Deg(camera.fovy)).0 / 2.0).tan();
+
+        Self {
+            center: instance.position,
+            axes,
+            size: distance * tan_half_fovy * 2.0 * SCREEN_SIZE,
+            view: (eye - instance.position).normalize(),
+        }
+    }
+
+    /// The nearest handle of `mode` on `ray`.
+    fn hit(&self, mode: GizmoMode, ray: &Ray) -> Option<Handle> {
+        let reach = self.size * PICK_WIDTH;
+        let mut hits = Vec::new();
+
+        match mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                for (i, axis) in self.axes.iter().enumerate() {
+                    let s = closest_on_line(ray, self.center, *axis)
+                        .map_or(0.0, |p| (p - self.center).dot(*axis))
+                        .clamp(0.0, self.size);
+                    let (t, distance) = ray_point_distance(ray, self.center + axis * s);
+                    if distance < reach {
+                        hits.push((t, Handle::Axis(i)));
+                    }
+                }
+            }
+            GizmoMode::Rotate => {}
+        }
+
+        match mode {
+            GizmoMode::Translate => {
+                for (i, axis) in self.axes.iter().enumerate() {
+                    let (u, v) = (self.axes[(i + 1) % 3], self.axes[(i + 2) % 3]);
+                    let inside = |x: f32| x >= PLANE.0 * self.size && x <= PLANE.1 * self.size;
+                    if let Some(p) = on_plane(ray, self.center, *axis) {
+                        let offset = p - self.center;
+                        if inside(offset.dot(u)) && inside(offset.dot(v)) {
+                            hits.push(((p - ray.origin).magnitude(), Handle::Plane(i)));
+                        }
+                    }
+                }
+            }
+            GizmoMode::Rotate => {
+                for (i, axis) in self.axes.iter().enumerate() {
+                    if let Some(p) = on_plane(ray, self.center, *axis) {
+                        if ((p - self.center).magnitude() - self.size).abs() < reach {
+                            hits.push(((p - ray.origin).magnitude(), Handle::Ring(i)));
+                        }
+                    }
+                }
+            }
+            GizmoMode::Scale => {
+                let (t, distance) = ray_point_distance(ray, self.center);
+                if distance < reach * 1.5 {
+                    hits.push((t, Handle::Center));
+                }
+            }
+        }
+
+        hits.into_iter()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, handle)| handle)
+    }
+
+    /// Where `ray` meets the surface `handle` moves along.
+    fn anchor(&self, handle: Handle, ray: &Ray) -> Option<Vector3<f32>> {
+        match handle {
+            Handle::Axis(i) => closest_on_line(ray, self.center, self.axes[i]),
+            Handle::Plane(i) | Handle::Ring(i) => on_plane(ray, self.center, self.axes[i]),
+            Handle::Center => on_plane(ray, self.center, self.view),
+        }
+    }
+}
+
+/// Point on the line through `origin` along unit `direction` that is
+/// closest to `ray`, `None` when they are parallel.
+fn closest_on_line(
+    ray: &Ray,
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+) -> Option<Vector3<f32>> {
+    let w = origin - ray.origin;
+    let b = direction.dot(ray.direction);
+    let denominator = 1.0 - b * b;
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+    let s = (b * w.dot(ray.direction) - w.dot(direction)) / denominator;
+    Some(origin + direction * s)
+}
+
+/// Where `ray` crosses the plane through `point` across `normal`, if in
+/// front of it.
+fn on_plane(ray: &Ray, point: Vector3<f32>, normal: Vector3<f32>) -> Option<Vector3<f32>> {
+    let denominator = normal.dot(ray.direction);
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+    let t = normal.dot(point - ray.origin) / denominator;
+    (t >= 0.0).then(|| ray.origin + ray.direction * t)
+}
+
+/// Distance along `ray` to the point nearest `point`, and how far apart
+/// they are.
+fn ray_point_distance(ray: &Ray, point: Vector3<f32>) -> (f32, f32) {
+    let t = (point - ray.origin).dot(ray.direction).max(0.0);
+    (t, (point - (ray.origin + ray.direction * t)).magnitude())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Drag {
+    mode: GizmoMode,
+    handle: Handle,
+    /// The instance as it was when the drag started.
+    start: Instance,
+    frame: Frame,
+    anchor: Vector3<f32>,
+}
+
+impl Drag {
+    /// The instance with the drag applied, `None` while the pointer is
+    /// somewhere the handle can't follow.
+    fn apply(&self, ray: &Ray) -> Option<Instance> {
+        let frame = &self.frame;
+        let point = frame.anchor(self.handle, ray)?;
+        let mut instance = self.start.clone();
+
+        match (self.handle, self.mode) {
+            (Handle::Axis(i), GizmoMode::Translate) => {
+                let axis = frame.axes[i];
+                instance.position += axis * (point - self.anchor).dot(axis);
+            }
+            (Handle::Plane(i), _) => {
+                let axis = frame.axes[i];
+                let delta = point - self.anchor;
+                instance.position += delta - axis * delta.dot(axis);
+            }
+            (Handle::Ring(i), _) => {
+                let axis = frame.axes[i];
+                let (from, to) = (self.anchor - frame.center, point - frame.center);
+                let angle = axis.dot(from.cross(to)).atan2(from.dot(to));
+                instance.rotation = cgmath::Quaternion::from_axis_angle(axis, cgmath::Rad(angle))
+                    * self.start.rotation;
+            }
+            (Handle::Axis(i), _) => {
+                let axis = frame.axes[i];
+                let from = (self.anchor - frame.center).dot(axis);
+                if from.abs() < 1e-6 {
+                    return None;
+                }
+                instance.scale[i] =
+                    (self.start.scale[i] * (point - frame.center).dot(axis) / from).max(MIN_SCALE);
+            }
+            (Handle::Center, _) => {
+                let from = (self.anchor - frame.center).magnitude();
+                if from < 1e-6 {
+                    return None;
+                }
+                let factor = (point - frame.center).magnitude() / from;
+                instance.scale = (self.start.scale * factor).map(|s| s.max(MIN_SCALE));
+            }
+        }
+
+        Some(instance)
+    }
+}
+
+/// Handles of a transform gizmo drawn on one instance, and the drag in
+/// progress.
+#[derive(Debug, Default)]
+pub(crate) struct Gizmo {
+    pub(crate) mode: Option<GizmoMode>,
+    hovered: Option<Handle>,
+    drag: Option<Drag>,
+    pressed: bool,
+}
+
+impl Gizmo {
+    pub(crate) fn set_mode(&mut self, mode: Option<GizmoMode>) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.hovered = None;
+            self.drag = None;
+        }
+    }
+
+    pub(crate) fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Follow the pointer on `ray`. Pressing on a handle starts a drag,
+    /// releasing ends it. Returns `instance` moved by the drag while one is
+    /// going on.
+    pub(crate) fn pointer(
+        &mut self,
+        ray: &Ray,
+        pressed: bool,
+        instance: Option<&Instance>,
+        camera: &Camera,
+    ) -> Option<Instance> {
+        let just_pressed = pressed && !self.pressed;
+        self.pressed = pressed;
+
+        let (mode, instance) = match (self.mode, instance) {
+            (Some(mode), Some(instance)) => (mode, instance),
+            _ => {
+                self.hovered = None;
+                self.drag = None;
+                return None;
+            }
+        };
+
+        if !pressed {
+            self.drag = None;
+        }
+        if let Some(drag) = &self.drag {
+            return drag.apply(ray);
+        }
+
+        let frame = Frame::new(mode, instance, camera);
+        self.hovered = frame.hit(mode, ray);
+        if just_pressed {
+            self.drag = self.hovered.and_then(|handle| {
+                Some(Drag {
+                    mode,
+                    handle,
+                    start: instance.clone(),
+                    frame,
+                    anchor: frame.anchor(handle, ray)?,
+                })
+            });
+        }
+
+        None
+    }
+
+    /// Triangles of the handles around `instance`, in world space.
+    pub(crate) fn vertices(&self, instance: &Instance, camera: &Camera) -> Vec<GizmoVertex> {
+        let mode = match self.mode {
+            Some(mode) => mode,
+            None => return Vec::new(),
+        };
+        let frame = Frame::new(mode, instance, camera);
+        let active = self.drag.as_ref().map(|drag| drag.handle).or(self.hovered);
+        let color = |handle: Handle, color: [f32; 4]| {
+            if active == Some(handle) {
+                ACTIVE_COLOR
+            } else {
+                color
+            }
+        };
+
+        let mut mesh = GizmoMesh {
+            vertices: Vec::new(),
+            view: frame.view,
+            thickness: frame.size * 0.02,
+        };
+        let (center, size) = (frame.center, frame.size);
+        for (i, axis) in frame.axes.iter().enumerate() {
+            let axis_color = color(Handle::Axis(i), COLORS[i]);
+            match mode {
+                GizmoMode::Translate => {
+                    mesh.line(center, center + axis * size * 0.8, axis_color);
+                    mesh.arrow_head(center + axis * size * 0.8, center + axis * size, axis_color);
+
+                    let (u, v) = (frame.axes[(i + 1) % 3], frame.axes[(i + 2) % 3]);
+                    let [r, g, b, _] = color(Handle::Plane(i), COLORS[i]);
+                    mesh.quad(
+                        [
+                            center + (u * PLANE.0 + v * PLANE.0) * size,
+                            center + (u * PLANE.1 + v * PLANE.0) * size,
+                            center + (u * PLANE.1 + v * PLANE.1) * size,
+                            center + (u * PLANE.0 + v * PLANE.1) * size,
+                        ],
+                        [r, g, b, 0.5],
+                    );
+                }
+                GizmoMode::Rotate => {
+                    let (u, v) = (frame.axes[(i + 1) % 3], frame.axes[(i + 2) % 3]);
+                    let ring_color = color(Handle::Ring(i), COLORS[i]);
+                    let segments = 64;
+                    let point = |k: usize| {
+                        let angle = k as f32 / segments as f32 * std::f32::consts::TAU;
+                        center + (u * angle.cos() + v * angle.sin()) * size
+                    };
+                    for k in 0..segments {
+                        mesh.line(point(k), point(k + 1), ring_color);
+                    }
+                }
+                GizmoMode::Scale => {
+                    mesh.line(center, center + axis * size, axis_color);
+                    mesh.cube(center + axis * size, &frame.axes, size * 0.06, axis_color);
+                }
+            }
+        }
+        if mode == GizmoMode::Scale {
+            mesh.cube(
+                center,
+                &frame.axes,
+                size * 0.08,
+                color(Handle::Center, CENTER_COLOR),
+            );
+        }
+
+        mesh.vertices
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct GizmoVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl GizmoVertex {
+    pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GizmoVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Builds the handles out of triangles, WebGL only draws 1 pixel lines.
+struct GizmoMesh {
+    vertices: Vec<GizmoVertex>,
+    view: Vector3<f32>,
+    thickness: f32,
+}
+
+impl GizmoMesh {
+    fn triangle(&mut self, corners: [Vector3<f32>; 3], color: [f32; 4]) {
+        self.vertices.extend(corners.map(|position| GizmoVertex {
+            position: position.into(),
+            color,
+        }));
+    }
+
+    fn quad(&mut self, [a, b, c, d]: [Vector3<f32>; 4], color: [f32; 4]) {
+        self.triangle([a, b, c], color);
+        self.triangle([a, c, d], color);
+    }
+
+    /// A strip from `a` to `b` turned towards the camera.
+    fn line(&mut self, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 4]) {
+        let side = (b - a).cross(self.view);
+        if side.magnitude2() == 0.0 {
+            return;
+        }
+        let side = side.normalize() * self.thickness / 2.0;
+        self.quad([a - side, b - side, b + side, a + side], color);
+    }
+
+    fn arrow_head(&mut self, base: Vector3<f32>, tip: Vector3<f32>, color: [f32; 4]) {
+        let side = (tip - base).cross(self.view);
+        if side.magnitude2() == 0.0 {
+            return;
+        }
+        let side = side.normalize() * self.thickness * 2.5;
+        self.triangle([base - side, tip, base + side], color);
+    }
+
+    fn cube(&mut self, center: Vector3<f32>, axes: &[Vector3<f32>; 3], half: f32, color: [f32; 4]) {
+        for i in 0..3 {
+            let (n, u, v) = (axes[i], axes[(i + 1) % 3], axes[(i + 2) % 3]);
+            for sign in [-1.0, 1.0] {
+                let face = center + n * half * sign;
+                self.quad(
+                    [
+                        face - u * half - v * half,
+                        face + u * half - v * half,
+                        face + u * half + v * half,
+                        face - u * half + v * half,
+                    ],
+                    color,
+                );
+            }
+        }
+    }
+}
+
+/// Drawn over everything, at the end of the scene pass.
+pub(crate) fn pipeline_state(scene: &pipeline::PipelineState) -> pipeline::PipelineState {
+    scene
+        .clone()
+        .blend(Some(wgpu::BlendState::ALPHA_BLENDING))
+        .cull_mode(None)
+        .depth(wgpu::CompareFunction::Always, false)
+}
+
+/// Draws `gizmo.wgsl` from the vertices of `Gizmo::vertices`.
+#[derive(Debug)]
+pub(crate) struct GizmoPass {
+    vertex_buffer: wgpu::Buffer,
+    /// Vertices `vertex_buffer` has room for.
+    capacity: usize,
+    vertex_count: u32,
+    pub(crate) shader: shader::Shader,
+    pub(crate) pipeline_layout: Rc<wgpu::PipelineLayout>,
+    pub(crate) pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl GizmoPass {
+    pub(crate) async fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut pipeline::PipelineCache,
+        camera_layout: &wgpu::BindGroupLayout,
+        scene_state: &pipeline::PipelineState,
+    ) -> Result<Self, anyhow::Error> {
+        let pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Gizmo Pipeline Layout"),
+                bind_group_layouts: &[camera_layout],
+                push_constant_ranges: &[],
+            },
+        ));
+        let shader = shader::Shader::from_file_name("Gizmo Shader", "gizmo.wgsl").await?;
+        let pipeline = pipeline_cache.get_or_create(
+            device,
+            &shader,
+            &pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[GizmoVertex::desc()],
+            &pipeline_state(scene_state),
+        )?;
+
+        Ok(Self {
+            vertex_buffer: Self::create_vertex_buffer(device, 1),
+            capacity: 1,
+            vertex_count: 0,
+            shader,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, vertex_count: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Gizmo Vertex Buffer"),
+            size: (std::mem::size_of::<GizmoVertex>() * vertex_count) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub(crate) fn set_vertices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: &[GizmoVertex],
+    ) {
+        if vertices.len() > self.capacity {
+            // Grow by half again so a drag doesn't reallocate every frame
+            self.capacity = vertices.len() * 3 / 2;
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    pub(crate) fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
@@ -0,0 +1,359 @@
+use wgpu::util::DeviceExt;
+
+use super::{
+    camera, culling,
+    instance::InstanceRaw,
+    lod,
+    model::{AlphaMode, DrawModel, Model},
+    shader::Shader,
+};
+
+/// Levels of detail `culling.wgsl` has counters for.
+const MAX_LEVELS: usize = 4;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullingUniform {
+    planes: [[f32; 4]; 6],
+    bounds: [f32; 4],
+    eye: [f32; 4],
+    thresholds: [f32; 4],
+    counts: [u32; 4],
+}
+
+/// Frustum culling and level of detail selection in a compute shader,
+/// drawn with `draw_indexed_indirect` so the visible counts never have to
+/// come back to the CPU.
+///
+/// Only the meshes that don't blend are drawn this way, the blended ones
+/// need their instances sorted and stay on the CPU path.
+#[derive(Debug)]
+pub(crate) struct GpuCulling {
+    instance_count: u32,
+    uniform: CullingUniform,
+    uniform_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    /// Visible instances, one region of `instance_count` per level.
+    visible_buffer: wgpu::Buffer,
+    counts_buffer: wgpu::Buffer,
+    /// A `DrawIndexedIndirect` per mesh of each level. The compute shader
+    /// only counts, the counts are copied into these.
+    draws: Vec<Vec<wgpu::Buffer>>,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    shader: Shader,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuCulling {
+    /// WebGL has neither compute shaders nor indirect draws, so this is
+    /// false under `downlevel_webgl2_defaults`.
+    pub(crate) fn supported(adapter: &wgpu::Adapter) -> bool {
+        adapter.get_downlevel_capabilities().flags.contains(
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION,
+        ) && adapter.limits().max_storage_buffers_per_shader_stage >= 4
+    }
+
+    pub(crate) async fn new(
+        device: &wgpu::Device,
+        instances: &[InstanceRaw],
+        model: &Model,
+    ) -> Result<Self, anyhow::Error> {
+        let instance_count = instances.len() as u32;
+        let levels = model.lod_count().min(MAX_LEVELS);
+        let thresholds = lod::SCREEN_SIZES.len().min(levels - 1);
+
+        let mut threshold_values = [0.0; 4];
+        threshold_values[..thresholds].copy_from_slice(&lod::SCREEN_SIZES[..thresholds]);
+        threshold_values[3] = lod::HYSTERESIS;
+        let bounds = model.bounding_sphere();
+        let uniform = CullingUniform {
+            planes: [[0.0; 4]; 6],
+            bounds: bounds.center.extend(bounds.radius).into(),
+            eye: [0.0; 4],
+            thresholds: threshold_values,
+            counts: [instance_count, thresholds as u32, 0, 0],
+        };
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Culling Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let counts_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Visible Counts Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; MAX_LEVELS]),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let draws = (0..levels)
+            .map(|level| {
+                model
+                    .lod(level)
+                    .iter()
+                    .map(|mesh| {
+                        let args = wgpu::util::DrawIndexedIndirect {
+                            vertex_count: mesh.num_elements,
+                            instance_count: 0,
+                            base_index: 0,
+                            vertex_offset: 0,
+                            base_instance: 0,
+                        };
+                        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some(&format!("{} Indirect Buffer", mesh.name)),
+                            contents: args.as_bytes(),
+                            usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("culling_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+                storage(4, false),
+            ],
+        });
+        let (instance_buffer, visible_buffer, bind_group) = Self::create_instance_buffers(
+            device,
+            &layout,
+            &uniform_buffer,
+            &counts_buffer,
+            instances,
+            levels,
+        );
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Culling Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = Shader::from_file_name("Culling Shader", "culling.wgsl").await?;
+        let pipeline = shader.create_compute_pipeline(device, &pipeline_layout, "cs_main")?;
+
+        Ok(Self {
+            instance_count,
+            uniform,
+            uniform_buffer,
+            instance_buffer,
+            visible_buffer,
+            counts_buffer,
+            draws,
+            layout,
+            bind_group,
+            pipeline_layout,
+            shader,
+            pipeline,
+        })
+    }
+
+    /// The buffers sized by the instance count, and the bind group with
+    /// them.
+    fn create_instance_buffers(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        counts_buffer: &wgpu::Buffer,
+        instances: &[InstanceRaw],
+        levels: usize,
+    ) -> (wgpu::Buffer, wgpu::Buffer, wgpu::BindGroup) {
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Culling Instance Buffer"),
+            contents: bytemuck::cast_slice(instances),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let visible_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: (std::mem::size_of_val(instances) * levels) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let lods_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instance Lods Buffer"),
+            contents: bytemuck::cast_slice(&vec![0u32; instances.len()]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("culling_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: visible_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: counts_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: lods_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        (instance_buffer, visible_buffer, bind_group)
+    }
+
+    /// Start over with a different number of instances.
+    pub(crate) fn set_instances(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[InstanceRaw],
+    ) {
+        (self.instance_buffer, self.visible_buffer, self.bind_group) =
+            Self::create_instance_buffers(
+                device,
+                &self.layout,
+                &self.uniform_buffer,
+                &self.counts_buffer,
+                instances,
+                self.draws.len(),
+            );
+        self.instance_count = instances.len() as u32;
+        self.uniform.counts[0] = self.instance_count;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    pub(crate) async fn reload_shader(
+        &mut self,
+        device: &wgpu::Device,
+    ) -> Result<(), anyhow::Error> {
+        let shader = match self.shader.refetch().await? {
+            Some(shader) => shader,
+            None => return Ok(()),
+        };
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = shader.create_compute_pipeline(device, &self.pipeline_layout, "cs_main");
+        if let Some(err) = device.pop_error_scope().await {
+            return Err(anyhow::anyhow!("culling.wgsl: {}", err));
+        }
+
+        self.pipeline = pipeline?;
+        self.shader = shader;
+        Ok(())
+    }
+
+    pub(crate) fn update(&mut self, queue: &wgpu::Queue, camera: &camera::Camera) {
+        let frustum = culling::Frustum::from_view_projection(camera.build_view_projection_matrix());
+        self.uniform.planes = frustum.planes();
+        let tan_half_fovy = (cgmath::Rad::from(cgmath::Deg(camera.fovy)).0 / 2.0).tan();
+        self.uniform.eye = [camera.eye.x, camera.eye.y, camera.eye.z, tan_half_fovy];
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    pub(crate) fn set_instance(&self, queue: &wgpu::Queue, index: usize, instance: InstanceRaw) {
+        queue.write_buffer(
+            &self.instance_buffer,
+            (index * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(&[instance]),
+        );
+    }
+
+    /// Record the culling pass, before the render pass that draws.
+    pub(crate) fn dispatch(&self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        queue.write_buffer(
+            &self.counts_buffer,
+            0,
+            bytemuck::cast_slice(&[0u32; MAX_LEVELS]),
+        );
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Culling Pass"),
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(self.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+
+        // `instance_count` is the second field of `DrawIndexedIndirect`
+        let count_size = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+        for (level, draws) in self.draws.iter().enumerate() {
+            for draw in draws {
+                encoder.copy_buffer_to_buffer(
+                    &self.counts_buffer,
+                    level as wgpu::BufferAddress * count_size,
+                    draw,
+                    count_size,
+                    count_size,
+                );
+            }
+        }
+    }
+
+    /// Draw the meshes of `model` that don't blend, with the pipeline and
+    /// any extra bind groups already set.
+    pub(crate) fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let region_size = (std::mem::size_of::<InstanceRaw>() * self.instance_count as usize)
+            as wgpu::BufferAddress;
+        for (level, draws) in self.draws.iter().enumerate() {
+            let start = level as wgpu::BufferAddress * region_size;
+            render_pass.set_vertex_buffer(1, self.visible_buffer.slice(start..start + region_size));
+            for (mesh, draw) in model.lod(level).iter().zip(draws) {
+                let material = &model.materials[mesh.material];
+                if material.alpha_mode != AlphaMode::Blend {
+                    render_pass.draw_mesh_indirect(
+                        mesh,
+                        material,
+                        draw,
+                        camera_bind_group,
+                        light_bind_group,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,18 @@
+/// Format the scene and the HDR half of the post stack render into.
+pub(crate) const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Operator that maps HDR colour down to the `[0, 1]` range of the surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Tonemapping {
+    /// Scale by exposure and clip.
+    Linear = 0,
+    Reinhard = 1,
+    Aces = 2,
+}
+
+impl Tonemapping {
+    /// Parameters of the `tonemap.wgsl` pass.
+    pub(crate) fn params(self, exposure: f32) -> [f32; 4] {
+        [exposure, self as u32 as f32, 0.0, 0.0]
+    }
+}
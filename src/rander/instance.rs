@@ -1,20 +1,41 @@
+use cgmath::SquareMatrix;
+
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct Instance {
     pub(crate) position: cgmath::Vector3<f32>,
     pub(crate) rotation: cgmath::Quaternion<f32>,
+    /// Along the model's own axes, applied before `rotation`.
+    pub(crate) scale: cgmath::Vector3<f32>,
 }
 
 impl Instance {
     pub(crate) fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
+                * cgmath::Matrix4::from(self.rotation)
+                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
+            .into(),
+            // Inverse transpose of the model matrix, the shader normalizes
+            normal: (cgmath::Matrix3::from(self.rotation)
+                * cgmath::Matrix3::from_diagonal(self.scale.map(|s| 1.0 / s)))
             .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
         }
     }
 }
 
+/// Sort `indices` into `instances` farthest from `eye` first, the order
+/// blended meshes are drawn in.
+pub(crate) fn back_to_front(
+    indices: &mut [usize],
+    instances: &[Instance],
+    eye: cgmath::Point3<f32>,
+) {
+    use cgmath::{EuclideanSpace, MetricSpace};
+
+    let distance = |i: usize| eye.distance2(cgmath::Point3::from_vec(instances[i].position));
+    indices.sort_by(|&a, &b| distance(b).total_cmp(&distance(a)));
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq,Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub(crate) struct InstanceRaw {
@@ -22,9 +43,6 @@ pub(crate) struct InstanceRaw {
     normal: [[f32; 3]; 3],
 }
 
-pub(crate) const NUM_INSTANCES_PER_ROW: u32 = 10;
-pub(crate) const SPACE_BETWEEN: f32 = 3.0;
-
 impl InstanceRaw {
     pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
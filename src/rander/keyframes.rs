@@ -0,0 +1,181 @@
+use cgmath::{Quaternion, Vector3, VectorSpace};
+
+/// How values between two keyframes are filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Interpolation {
+    /// Hold each value until the next keyframe.
+    Step,
+    /// Lerp, or slerp for rotations.
+    Linear,
+}
+
+pub(crate) trait Interpolate: Copy {
+    /// `self` at `t` 0, `other` at 1.
+    fn interpolate(self, other: Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vector3<f32> {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Interpolate for Quaternion<f32> {
+    /// Along the shorter arc.
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+/// Values at increasing times, in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Keyframes<T> {
+    pub(crate) times: Vec<f32>,
+    pub(crate) values: Vec<T>,
+    pub(crate) interpolation: Interpolation,
+}
+
+impl<T: Interpolate> Keyframes<T> {
+    /// `None` when `times` and `values` don't pair up or there are no
+    /// keyframes at all.
+    pub(crate) fn new(
+        times: Vec<f32>,
+        values: Vec<T>,
+        interpolation: Interpolation,
+    ) -> Option<Self> {
+        (!times.is_empty() && times.len() == values.len()).then_some(Self {
+            times,
+            values,
+            interpolation,
+        })
+    }
+
+    /// Time of the last keyframe.
+    pub(crate) fn duration(&self) -> f32 {
+        self.times[self.times.len() - 1]
+    }
+
+    /// The value at `time`, held at the first and last keyframes outside
+    /// of them.
+    pub(crate) fn sample(&self, time: f32) -> T {
+        self.sample_eased(time, |t| t)
+    }
+
+    /// Like `sample`, with `ease` reshaping the progress from one keyframe
+    /// to the next.
+    pub(crate) fn sample_eased(&self, time: f32, ease: impl Fn(f32) -> f32) -> T {
+        // Index of the first keyframe after `time`
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return self.values[0];
+        }
+        if next == self.times.len() {
+            return self.values[next - 1];
+        }
+
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        match self.interpolation {
+            Interpolation::Step => self.values[next - 1],
+            Interpolation::Linear => {
+                let t = (time - start) / (end - start);
+                self.values[next - 1].interpolate(self.values[next], ease(t))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, InnerSpace, Rotation3};
+
+    use super::*;
+
+    fn linear() -> Keyframes<f32> {
+        Keyframes::new(
+            vec![0.0, 1.0, 3.0],
+            vec![0.0, 10.0, 30.0],
+            Interpolation::Linear,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn lerps_between_keyframes() {
+        let keyframes = linear();
+        assert_eq!(keyframes.sample(0.0), 0.0);
+        assert_eq!(keyframes.sample(0.5), 5.0);
+        assert_eq!(keyframes.sample(1.0), 10.0);
+        assert_eq!(keyframes.sample(2.0), 20.0);
+        assert_eq!(keyframes.duration(), 3.0);
+    }
+
+    #[test]
+    fn holds_outside_the_keyframes() {
+        let keyframes = linear();
+        assert_eq!(keyframes.sample(-1.0), 0.0);
+        assert_eq!(keyframes.sample(4.0), 30.0);
+    }
+
+    #[test]
+    fn steps_hold_until_the_next_keyframe() {
+        let keyframes = Keyframes {
+            interpolation: Interpolation::Step,
+            ..linear()
+        };
+        assert_eq!(keyframes.sample(0.99), 0.0);
+        assert_eq!(keyframes.sample(1.0), 10.0);
+        assert_eq!(keyframes.sample(2.9), 10.0);
+    }
+
+    #[test]
+    fn single_keyframe_is_constant() {
+        let keyframes = Keyframes::new(vec![0.5], vec![7.0], Interpolation::Linear).unwrap();
+        assert_eq!(keyframes.sample(0.0), 7.0);
+        assert_eq!(keyframes.sample(9.0), 7.0);
+    }
+
+    #[test]
+    fn rejects_mismatched_keyframes() {
+        assert!(Keyframes::new(vec![0.0, 1.0], vec![0.0], Interpolation::Linear).is_none());
+        assert!(Keyframes::<f32>::new(vec![], vec![], Interpolation::Linear).is_none());
+    }
+
+    #[test]
+    fn slerps_rotations() {
+        let axis = Vector3::unit_y();
+        let keyframes = Keyframes::new(
+            vec![0.0, 1.0],
+            vec![
+                Quaternion::from_axis_angle(axis, Deg(0.0)),
+                Quaternion::from_axis_angle(axis, Deg(90.0)),
+            ],
+            Interpolation::Linear,
+        )
+        .unwrap();
+
+        let half = keyframes.sample(0.5);
+        let expected = Quaternion::from_axis_angle(axis, Deg(45.0));
+        assert!((half - expected).magnitude() < 1e-5);
+        assert!((half.magnitude() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn slerps_the_short_way_round() {
+        let axis = Vector3::unit_z();
+        let start = Quaternion::from_axis_angle(axis, Deg(10.0));
+        // The same rotation as -10 degrees, with every component negated
+        let end = -Quaternion::from_axis_angle(axis, Deg(-10.0));
+        let keyframes =
+            Keyframes::new(vec![0.0, 1.0], vec![start, end], Interpolation::Linear).unwrap();
+
+        let half = keyframes.sample(0.5);
+        let identity = Quaternion::from_axis_angle(axis, Deg(0.0));
+        assert!(half.dot(identity).abs() > 1.0 - 1e-5);
+    }
+}
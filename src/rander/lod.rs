@@ -0,0 +1,217 @@
+use std::{collections::HashMap, ops::Range};
+
+use cgmath::{InnerSpace, MetricSpace};
+
+use super::{culling::Sphere, model::ModelVertex};
+
+/// Screen size, as a fraction of the viewport height, under which each
+/// level after the first takes over.
+pub(crate) const SCREEN_SIZES: [f32; 2] = [0.25, 0.1];
+/// Grid cell of each generated level, as a fraction of the model's
+/// bounding sphere diameter.
+pub(crate) const CELL_SIZES: [f32; 2] = [1.0 / 32.0, 1.0 / 12.0];
+/// How far past a threshold an instance has to go before it switches
+/// back, so instances sitting on a threshold don't flicker.
+pub(crate) const HYSTERESIS: f32 = 0.1;
+
+/// Fraction of the viewport height covered by `sphere` seen from `eye`.
+pub(crate) fn screen_size(
+    sphere: &Sphere,
+    eye: cgmath::Point3<f32>,
+    fovy: cgmath::Rad<f32>,
+) -> f32 {
+    use cgmath::EuclideanSpace;
+
+    let distance = eye.distance(cgmath::Point3::from_vec(sphere.center));
+    if distance <= sphere.radius {
+        return f32::INFINITY;
+    }
+    sphere.radius / (distance * (fovy.0 / 2.0).tan())
+}
+
+/// Level out of `levels` for an instance `size` big on screen that used
+/// `previous` last frame.
+pub(crate) fn select(previous: usize, size: f32, levels: usize) -> usize {
+    let thresholds = &SCREEN_SIZES[..SCREEN_SIZES.len().min(levels.saturating_sub(1))];
+
+    // Within the band of the previous level, widened by the hysteresis
+    let previous = previous.min(thresholds.len());
+    let above_lower = thresholds
+        .get(previous)
+        .map_or(true, |t| size >= t * (1.0 - HYSTERESIS));
+    let below_upper = previous
+        .checked_sub(1)
+        .map_or(true, |i| size <= thresholds[i] * (1.0 + HYSTERESIS));
+    if above_lower && below_upper {
+        return previous;
+    }
+
+    thresholds.iter().filter(|t| size < **t).count()
+}
+
+/// Stretches of the same level in `levels`, with the range of indices
+/// each covers.
+pub(crate) fn runs(levels: impl IntoIterator<Item = usize>) -> Vec<(usize, Range<u32>)> {
+    let mut runs: Vec<(usize, Range<u32>)> = Vec::new();
+    for (i, level) in levels.into_iter().enumerate() {
+        let i = i as u32;
+        match runs.last_mut() {
+            Some((last, range)) if *last == level => range.end = i + 1,
+            _ => runs.push((level, i..i + 1)),
+        }
+    }
+    runs
+}
+
+/// Vertex clustering: vertices are snapped to a grid of `cell` sized
+/// cells and merged, triangles that collapse are dropped.
+///
+/// Vertices facing different ways aren't merged, so hard edges and most
+/// texture seams survive.
+pub(crate) fn simplify(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    cell: f32,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut clusters = HashMap::new();
+    let mut merged: Vec<(ModelVertex, u32)> = Vec::new();
+    let remap = vertices
+        .iter()
+        .map(|v| {
+            let key = (
+                (v.position[0] / cell).floor() as i32,
+                (v.position[1] / cell).floor() as i32,
+                (v.position[2] / cell).floor() as i32,
+                facing(v.normal),
+            );
+            *clusters.entry(key).or_insert_with(|| {
+                merged.push((
+                    ModelVertex {
+                        position: [0.0; 3],
+                        normal: [0.0; 3],
+                        ..*v
+                    },
+                    0,
+                ));
+                merged.len() as u32 - 1
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Each cluster is the average of its vertices
+    for (v, &i) in vertices.iter().zip(&remap) {
+        let (sum, count) = &mut merged[i as usize];
+        for axis in 0..3 {
+            sum.position[axis] += v.position[axis];
+            sum.normal[axis] += v.normal[axis];
+        }
+        *count += 1;
+    }
+    let vertices = merged
+        .into_iter()
+        .map(|(mut v, count)| {
+            v.position = (cgmath::Vector3::from(v.position) / count as f32).into();
+            v.normal = cgmath::Vector3::from(v.normal).normalize().into();
+            v
+        })
+        .collect();
+
+    let indices = indices
+        .chunks(3)
+        .map(|c| {
+            [
+                remap[c[0] as usize],
+                remap[c[1] as usize],
+                remap[c[2] as usize],
+            ]
+        })
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .flatten()
+        .collect();
+
+    (vertices, indices)
+}
+
+/// Which of the six axis directions a normal is closest to.
+fn facing(normal: [f32; 3]) -> u8 {
+    let axis = (0..3)
+        .max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs()))
+        .unwrap_or(0);
+    axis as u8 * 2 + (normal[axis] < 0.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], normal: [f32; 3]) -> ModelVertex {
+        ModelVertex {
+            position,
+            tex_coords: [0.0; 2],
+            normal,
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        }
+    }
+
+    #[test]
+    fn select_stays_inside_the_band() {
+        // Just under the first threshold, but within the hysteresis
+        assert_eq!(select(0, 0.24, 3), 0);
+        // Just over it coming from the next level
+        assert_eq!(select(1, 0.26, 3), 1);
+        assert_eq!(select(1, 0.095, 3), 1);
+        assert_eq!(select(2, 0.105, 3), 2);
+    }
+
+    #[test]
+    fn select_switches_outside_the_band() {
+        assert_eq!(select(0, 0.2, 3), 1);
+        assert_eq!(select(0, 0.05, 3), 2);
+        assert_eq!(select(1, 0.3, 3), 0);
+        assert_eq!(select(2, 0.5, 3), 0);
+        assert_eq!(select(1, 0.05, 3), 2);
+        // Levels that don't exist any more
+        assert_eq!(select(5, 0.05, 3), 2);
+    }
+
+    #[test]
+    fn select_with_fewer_levels() {
+        assert_eq!(select(0, 0.01, 1), 0);
+        assert_eq!(select(0, f32::INFINITY, 1), 0);
+        assert_eq!(select(2, 0.01, 1), 0);
+        assert_eq!(select(0, 0.01, 2), 1);
+    }
+
+    #[test]
+    fn simplify_merges_same_facing_vertices() {
+        let up = [0.0, 0.0, 1.0];
+        let down = [0.0, 0.0, -1.0];
+        let vertices = [
+            vertex([0.1, 0.1, 0.0], up),
+            vertex([0.2, 0.3, 0.0], up),
+            vertex([1.5, 0.1, 0.0], up),
+            vertex([0.1, 1.5, 0.0], up),
+            // In the first vertex's cell, but facing the other way
+            vertex([0.15, 0.2, 0.0], down),
+        ];
+        let (vertices, indices) = simplify(&vertices, &[0, 2, 3, 0, 1, 2, 4, 2, 3], 1.0);
+
+        assert_eq!(vertices.len(), 4);
+        assert!((vertices[0].position[0] - 0.15).abs() < 1e-6);
+        assert!((vertices[0].position[1] - 0.2).abs() < 1e-6);
+        assert_eq!(vertices[0].normal, up);
+        assert_eq!(vertices[3].normal, down);
+        // The triangle between the first two vertices collapsed
+        assert_eq!(indices, [0, 1, 2, 3, 1, 2]);
+    }
+
+    #[test]
+    fn runs_of_levels() {
+        assert_eq!(runs([]), Vec::new());
+        assert_eq!(
+            runs([2, 2, 0, 1, 1, 1, 0]),
+            vec![(2, 0..2), (0, 2..3), (1, 3..6), (0, 6..7)]
+        );
+    }
+}
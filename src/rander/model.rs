@@ -1,10 +1,15 @@
 use wgpu::util::DeviceExt;
 
 use super::texture::{self, TextureImage};
+use super::{culling, lod};
 
 #[derive(Debug)]
 pub(crate) struct Model {
+    /// File the model was loaded from.
+    pub(crate) name: String,
     pub(crate) meshes: Vec<Mesh>,
+    /// Coarser versions of `meshes`, each one cheaper than the last.
+    pub(crate) lods: Vec<Vec<Mesh>>,
     pub(crate) materials: Vec<Material>,
 }
 
@@ -20,13 +25,133 @@ impl Model {
         queue: &wgpu::Queue,
         layout: &wgpu::BindGroupLayout,
     ) -> Result<Self, anyhow::Error> {
+        Self::from_file_names(&[name], device, queue, layout).await
+    }
+
+    /// The first file is the full detail model, the rest are its levels of
+    /// detail from fine to coarse and only their geometry is used. With a
+    /// single file the levels are generated with `lod::simplify`.
+    pub(crate) async fn from_file_names(
+        names: &[&str],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Result<Self, anyhow::Error> {
+        let (name, lod_names) = names
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("a model needs at least one file"))?;
+        let (models, obj_materials) = Self::load_obj(name).await?;
+
+        let mut materials = Vec::new();
+        for m in obj_materials? {
+            materials.push(Material::from_tobj_materials(
+                &m,
+                texture::TextureImage::from_file_name(&m.diffuse_texture).await?,
+                texture::TextureImage::from_file_name(&m.normal_texture).await?,
+                device,
+                queue,
+                layout,
+            )?)
+        }
+
+        let meshes = models
+            .iter()
+            .map(|m| Mesh::from_tobj_model(&m.name, m, device))
+            .collect::<Vec<_>>();
+
+        let mut lods = Vec::new();
+        if lod_names.is_empty() {
+            let diameter = meshes
+                .iter()
+                .map(|mesh| mesh.bounds)
+                .reduce(culling::Aabb::union)
+                .map_or(0.0, |aabb| aabb.bounding_sphere().radius * 2.0);
+            let vertices = models
+                .iter()
+                .map(Mesh::vertices_from_tobj)
+                .collect::<Vec<_>>();
+            for cell in lod::CELL_SIZES {
+                let lod = models
+                    .iter()
+                    .zip(&vertices)
+                    .filter_map(|(m, vertices)| {
+                        let (vertices, indices) =
+                            lod::simplify(vertices, &m.mesh.indices, cell * diameter);
+                        // Nothing left of it at this size
+                        (!indices.is_empty()).then(|| {
+                            Mesh::from_vertices(
+                                &m.name,
+                                &vertices,
+                                &indices,
+                                m.mesh.material_id.unwrap_or(0),
+                                device,
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                if lod.is_empty() {
+                    break;
+                }
+                lods.push(lod);
+            }
+        } else {
+            for name in lod_names {
+                let (models, _) = Self::load_obj(name).await?;
+                lods.push(
+                    models
+                        .iter()
+                        .map(|m| Mesh::from_tobj_model(&m.name, m, device))
+                        .collect(),
+                );
+            }
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            meshes,
+            lods,
+            materials,
+        })
+    }
+
+    /// Number of levels of detail, including the full one.
+    pub(crate) fn lod_count(&self) -> usize {
+        self.lods.len() + 1
+    }
+
+    /// Whether any material blends, see `AlphaMode::Blend`.
+    pub(crate) fn blends(&self) -> bool {
+        self.materials
+            .iter()
+            .any(|material| material.alpha_mode == AlphaMode::Blend)
+    }
+
+    /// Meshes of a level of detail, 0 is the full one.
+    pub(crate) fn lod(&self, level: usize) -> &[Mesh] {
+        match level {
+            0 => &self.meshes,
+            _ => &self.lods[level - 1],
+        }
+    }
+
+    /// The models in `name` and its materials, which may fail to load on
+    /// their own.
+    async fn load_obj(
+        name: &str,
+    ) -> Result<
+        (
+            Vec<tobj::Model>,
+            Result<Vec<tobj::Material>, tobj::LoadError>,
+        ),
+        anyhow::Error,
+    > {
         let obj_text = Resources::from_path(&format!("/static/{}", name))
             .request_string()
             .await?;
         let obj_cursor = Cursor::new(obj_text);
         let mut obj_reader = BufReader::new(obj_cursor);
 
-        let (models, obj_materials) = tobj::load_obj_buf_async(
+        Ok(tobj::load_obj_buf_async(
             &mut obj_reader,
             &tobj::LoadOptions {
                 triangulate: true,
@@ -41,26 +166,66 @@ impl Model {
                 tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
             },
         )
-        .await?;
+        .await?)
+    }
 
-        let mut materials = Vec::new();
-        for m in obj_materials? {
-            materials.push(Material::from_tobj_materials(
-                &m.name,
-                texture::TextureImage::from_file_name(&m.diffuse_texture).await?,
-                texture::TextureImage::from_file_name(&m.normal_texture).await?,
-                device,
-                queue,
-                layout,
-            )?)
-        }
+    /// Sphere around every mesh, in model space.
+    pub(crate) fn bounding_sphere(&self) -> culling::Sphere {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(culling::Aabb::union)
+            .map_or(
+                culling::Sphere {
+                    center: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                    radius: 0.0,
+                },
+                |aabb| aabb.bounding_sphere(),
+            )
+    }
+}
 
-        let meshes = models
-            .into_iter()
-            .map(|m| Mesh::from_tobj_model(&m.name, &m, device))
-            .collect::<Vec<_>>();
+/// How a material's alpha is used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AlphaMode {
+    Opaque,
+    /// Fragments under the cutoff are discarded, the rest are opaque.
+    Mask(f32),
+    /// Drawn after everything opaque, sorted back to front.
+    Blend,
+}
 
-        Ok(Self { meshes, materials })
+impl AlphaMode {
+    /// Read from the `alpha_mode` (`opaque`, `mask` or `blend`) and
+    /// `alpha_cutoff` statements, which aren't standard MTL. Without them
+    /// a material blends if its `d` is under 1 or its texture has alpha.
+    fn from_tobj(
+        material: &tobj::Material,
+        texture_has_alpha: bool,
+    ) -> Result<Self, anyhow::Error> {
+        let cutoff = match material.unknown_param.get("alpha_cutoff") {
+            Some(cutoff) => cutoff.trim().parse()?,
+            None => 0.5,
+        };
+
+        let mode = material.unknown_param.get("alpha_mode").map(|m| m.trim());
+        Ok(match mode {
+            Some("opaque") => AlphaMode::Opaque,
+            Some("mask") => AlphaMode::Mask(cutoff),
+            Some("blend") => AlphaMode::Blend,
+            Some(mode) => anyhow::bail!("{}: unknown alpha_mode {:?}", material.name, mode),
+            None if material.dissolve < 1.0 || texture_has_alpha => AlphaMode::Blend,
+            None => AlphaMode::Opaque,
+        })
+    }
+
+    /// x: dissolve, y: cutoff, z: 1 when blending. See `material` in `shader.wgsl`.
+    fn params(self, dissolve: f32) -> [f32; 4] {
+        match self {
+            AlphaMode::Opaque => [dissolve, 0.0, 0.0, 0.0],
+            AlphaMode::Mask(cutoff) => [dissolve, cutoff, 0.0, 0.0],
+            AlphaMode::Blend => [dissolve, 0.0, 1.0, 0.0],
+        }
     }
 }
 
@@ -69,18 +234,49 @@ pub(crate) struct Material {
     pub(crate) name: String,
     pub(crate) diffuse_texture: texture::Texture,
     pub normal_texture: texture::Texture,
+    pub(crate) alpha_mode: AlphaMode,
     pub(crate) bind_group: wgpu::BindGroup,
 }
 
 impl Material {
     fn from_tobj_materials(
+        material: &tobj::Material,
+        texture_img: TextureImage,
+        normal_img: TextureImage,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Result<Self, anyhow::Error> {
+        let alpha_mode = AlphaMode::from_tobj(material, texture_img.has_alpha())?;
+        Self::new(
+            &material.name,
+            alpha_mode,
+            material.dissolve,
+            texture_img,
+            normal_img,
+            device,
+            queue,
+            layout,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
         name: &str,
+        alpha_mode: AlphaMode,
+        dissolve: f32,
         texture_img: TextureImage,
         normal_img: TextureImage,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         layout: &wgpu::BindGroupLayout,
     ) -> Result<Self, anyhow::Error> {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&alpha_mode.params(dissolve)),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
         let diffuse_texture = texture::Texture::from_image(device, queue, texture_img, None, false)?;
         let normal_texture = texture::Texture::from_image(device, queue, normal_img, None, true)?;
 
@@ -103,6 +299,10 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buffer.as_entire_binding(),
+                },
             ],
             label: Some(name),
         });
@@ -111,6 +311,7 @@ impl Material {
             name: name.to_string(),
             diffuse_texture,
             normal_texture,
+            alpha_mode,
             bind_group,
         })
     }
@@ -123,10 +324,23 @@ pub(crate) struct Mesh {
     pub(crate) index_buffer: wgpu::Buffer,
     pub(crate) num_elements: u32,
     pub(crate) material: usize,
+    pub(crate) bounds: culling::Aabb,
+    /// Model space corners of every triangle, kept for picking.
+    pub(crate) triangles: Vec<[cgmath::Vector3<f32>; 3]>,
 }
 
 impl Mesh {
     fn from_tobj_model(name: &str, model: &tobj::Model, device: &wgpu::Device) -> Self {
+        Self::from_vertices(
+            name,
+            &Self::vertices_from_tobj(model),
+            &model.mesh.indices,
+            model.mesh.material_id.unwrap_or(0),
+            device,
+        )
+    }
+
+    fn vertices_from_tobj(model: &tobj::Model) -> Vec<ModelVertex> {
         let mut vertices = (0..model.mesh.positions.len() / 3)
             .map(|i| ModelVertex {
                 position: [
@@ -144,93 +358,137 @@ impl Mesh {
                 bitangent: [0.0; 3],
             })
             .collect::<Vec<_>>();
+        compute_tangents(&mut vertices, &model.mesh.indices);
+        vertices
+    }
 
-        let indices = &model.mesh.indices;
-        let mut triangles_included = vec![0; vertices.len()];
-
-        // Calculate tangents and bitangets. We're going to
-        // use the triangles, so we need to loop through the
-        // indices in chunks of 3
-        for c in indices.chunks(3) {
-            let v0 = vertices[c[0] as usize];
-            let v1 = vertices[c[1] as usize];
-            let v2 = vertices[c[2] as usize];
-
-            let pos0: cgmath::Vector3<_> = v0.position.into();
-            let pos1: cgmath::Vector3<_> = v1.position.into();
-            let pos2: cgmath::Vector3<_> = v2.position.into();
-
-            let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
-            let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
-            let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
-
-            // Calculate the edges of the triangle
-            let delta_pos1 = pos1 - pos0;
-            let delta_pos2 = pos2 - pos0;
-
-            // This will give us a direction to calculate the
-            // tangent and bitangent
-            let delta_uv1 = uv1 - uv0;
-            let delta_uv2 = uv2 - uv0;
-
-            // Solving the following system of equations will
-            // give us the tangent and bitangent.
-            //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
-            //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
-            // Luckily, the place I found this equation provided
-            // the solution!
-            let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
-            let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
-            // We flip the bitangent to enable right-handed normal
-            // maps with wgpu texture coordinate system
-            let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
-
-            // We'll use the same tangent/bitangent for each vertex in the triangle
-            vertices[c[0] as usize].tangent =
-                (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
-            vertices[c[1] as usize].tangent =
-                (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
-            vertices[c[2] as usize].tangent =
-                (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
-            vertices[c[0] as usize].bitangent =
-                (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
-            vertices[c[1] as usize].bitangent =
-                (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
-            vertices[c[2] as usize].bitangent =
-                (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
-
-            // Used to average the tangents/bitangents
-            triangles_included[c[0] as usize] += 1;
-            triangles_included[c[1] as usize] += 1;
-            triangles_included[c[2] as usize] += 1;
-        }
-
-        // Average the tangents/bitangents
-        for (i, n) in triangles_included.into_iter().enumerate() {
-            let denom = 1.0 / n as f32;
-            let mut v = &mut vertices[i];
-            v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
-            v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
-        }
-
+    pub(crate) fn from_vertices<V: Vertex + bytemuck::Pod>(
+        name: &str,
+        vertices: &[V],
+        indices: &[u32],
+        material: usize,
+        device: &wgpu::Device,
+    ) -> Self {
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(&format!("{:?} Vertex Buffer", name)),
-            contents: bytemuck::cast_slice(&vertices),
+            contents: bytemuck::cast_slice(vertices),
             usage: wgpu::BufferUsages::VERTEX,
         });
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(&format!("{:?} Index Buffer", name)),
-            contents: bytemuck::cast_slice(&model.mesh.indices),
+            contents: bytemuck::cast_slice(indices),
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let bounds = culling::Aabb::from_points(vertices.iter().map(|v| v.position().into()))
+            .unwrap_or(culling::Aabb {
+                min: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                max: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            });
+
+        let triangles = indices
+            .chunks(3)
+            .map(|c| [c[0], c[1], c[2]].map(|i| vertices[i as usize].position().into()))
+            .collect();
+
         Self {
             name: name.to_string(),
             vertex_buffer,
             index_buffer,
-            num_elements: model.mesh.indices.len() as u32,
-            material: model.mesh.material_id.unwrap_or(0),
+            num_elements: indices.len() as u32,
+            material,
+            bounds,
+            triangles,
+        }
+    }
+}
+
+/// Tangents and bitangents of every vertex from its triangles' UVs.
+pub(crate) fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    use cgmath::InnerSpace;
+
+    let mut triangles_included = vec![0; vertices.len()];
+
+    // Calculate tangents and bitangets. We're going to
+    // use the triangles, so we need to loop through the
+    // indices in chunks of 3
+    for c in indices.chunks(3) {
+        let v0 = vertices[c[0] as usize];
+        let v1 = vertices[c[1] as usize];
+        let v2 = vertices[c[2] as usize];
+
+        let pos0: cgmath::Vector3<_> = v0.position.into();
+        let pos1: cgmath::Vector3<_> = v1.position.into();
+        let pos2: cgmath::Vector3<_> = v2.position.into();
+
+        let uv0: cgmath::Vector2<_> = v0.tex_coords.into();
+        let uv1: cgmath::Vector2<_> = v1.tex_coords.into();
+        let uv2: cgmath::Vector2<_> = v2.tex_coords.into();
+
+        // Calculate the edges of the triangle
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+
+        // This will give us a direction to calculate the
+        // tangent and bitangent
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving the following system of equations will
+        // give us the tangent and bitangent.
+        //     delta_pos1 = delta_uv1.x * T + delta_u.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        // Luckily, the place I found this equation provided
+        // the solution!
+        let r = 1.0 / (delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x);
+        // No UV area, no tangent frame
+        if !r.is_finite() {
+            continue;
+        }
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // We flip the bitangent to enable right-handed normal
+        // maps with wgpu texture coordinate system
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        // We'll use the same tangent/bitangent for each vertex in the triangle
+        vertices[c[0] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[0] as usize].tangent)).into();
+        vertices[c[1] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[1] as usize].tangent)).into();
+        vertices[c[2] as usize].tangent =
+            (tangent + cgmath::Vector3::from(vertices[c[2] as usize].tangent)).into();
+        vertices[c[0] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[0] as usize].bitangent)).into();
+        vertices[c[1] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[1] as usize].bitangent)).into();
+        vertices[c[2] as usize].bitangent =
+            (bitangent + cgmath::Vector3::from(vertices[c[2] as usize].bitangent)).into();
+
+        // Used to average the tangents/bitangents
+        triangles_included[c[0] as usize] += 1;
+        triangles_included[c[1] as usize] += 1;
+        triangles_included[c[2] as usize] += 1;
+    }
+
+    // Average the tangents/bitangents
+    for (i, n) in triangles_included.into_iter().enumerate() {
+        let mut v = &mut vertices[i];
+        if n == 0 {
+            // Any frame around the normal will do without a normal map
+            let normal = cgmath::Vector3::from(v.normal);
+            let other = if normal.x.abs() < 0.9 {
+                cgmath::Vector3::unit_x()
+            } else {
+                cgmath::Vector3::unit_y()
+            };
+            let tangent = other.cross(normal).normalize();
+            v.tangent = tangent.into();
+            v.bitangent = (-normal.cross(tangent)).into();
+            continue;
         }
+        let denom = 1.0 / n as f32;
+        v.tangent = (cgmath::Vector3::from(v.tangent) * denom).into();
+        v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
     }
 }
 
@@ -250,6 +508,16 @@ pub(crate) trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    /// Instance count and the rest come from a `DrawIndexedIndirect` in
+    /// `indirect_buffer`.
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect_buffer: &'a wgpu::Buffer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 
     fn draw_model(
         &mut self,
@@ -257,9 +525,21 @@ pub(crate) trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    /// `lod` picks the level of detail, see `Model::lod`.
     fn draw_model_instanced(
         &mut self,
         model: &'a Model,
+        lod: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// Only the meshes whose material blends, or only those that don't.
+    fn draw_model_instanced_blended(
+        &mut self,
+        model: &'a Model,
+        lod: usize,
+        blended: bool,
         instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
@@ -304,23 +584,40 @@ where
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect_buffer: &'b wgpu::Buffer,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect_buffer, 0);
+    }
+
     fn draw_model(
         &mut self,
         model: &'b Model,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.draw_model_instanced(model, 0..1, camera_bind_group, light_bind_group);
+        self.draw_model_instanced(model, 0, 0..1, camera_bind_group, light_bind_group);
     }
 
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
+        lod: usize,
         instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'b wgpu::BindGroup,
     ) {
-        for mesh in &model.meshes {
+        for mesh in model.lod(lod) {
             let material = &model.materials[mesh.material];
             self.draw_mesh_instanced(
                 mesh,
@@ -331,6 +628,28 @@ where
             );
         }
     }
+    fn draw_model_instanced_blended(
+        &mut self,
+        model: &'b Model,
+        lod: usize,
+        blended: bool,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in model.lod(lod) {
+            let material = &model.materials[mesh.material];
+            if (material.alpha_mode == AlphaMode::Blend) == blended {
+                self.draw_mesh_instanced(
+                    mesh,
+                    material,
+                    instances.clone(),
+                    camera_bind_group,
+                    light_bind_group,
+                );
+            }
+        }
+    }
     fn draw_model_instanced_with_material(
         &mut self,
         model: &'b Model,
@@ -347,6 +666,7 @@ where
 
 pub(crate) trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
+    fn position(&self) -> [f32; 3];
 }
 
 #[repr(C)]
@@ -394,4 +714,89 @@ impl Vertex for ModelVertex {
             ],
         }
     }
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+/// A `ModelVertex` moved by up to four joints of a `skeleton::Skeleton`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct SkinnedVertex {
+    pub(crate) position: [f32; 3],
+    pub(crate) tex_coords: [f32; 2],
+    pub(crate) normal: [f32; 3],
+    pub(crate) tangent: [f32; 3],
+    pub(crate) bitangent: [f32; 3],
+    /// Indices into `Skeleton::joints`.
+    pub(crate) joints: [u32; 4],
+    /// Sum to 1.
+    pub(crate) weights: [f32; 4],
+}
+
+impl SkinnedVertex {
+    pub(crate) fn new(vertex: ModelVertex, joints: [u32; 4], weights: [f32; 4]) -> Self {
+        Self {
+            position: vertex.position,
+            tex_coords: vertex.tex_coords,
+            normal: vertex.normal,
+            tangent: vertex.tangent,
+            bitangent: vertex.bitangent,
+            joints,
+            weights,
+        }
+    }
+}
+
+impl Vertex for SkinnedVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            // The same as `ModelVertex`, then after the instance attributes
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 11]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 14]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
 }
@@ -0,0 +1,288 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use super::{
+    hdr,
+    instance::{Instance, InstanceRaw},
+    model::{Model, ModelVertex, Vertex},
+    pipeline, shader, texture,
+};
+
+/// How selected instances are outlined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Outline {
+    /// Linear, tonemapped along with the scene.
+    pub(crate) color: [f32; 3],
+    /// In canvas pixels.
+    pub(crate) width: f32,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.5, 0.0],
+            width: 3.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    size: [f32; 4],
+}
+
+/// Stencil value under the selected instances.
+const SELECTED: u32 = 1;
+
+/// Marks the selected instances in the stencil, ignoring depth so
+/// whatever covers them is marked too.
+pub(crate) fn mark_state(scene: &pipeline::PipelineState) -> pipeline::PipelineState {
+    let mark = wgpu::StencilFaceState {
+        compare: wgpu::CompareFunction::Always,
+        fail_op: wgpu::StencilOperation::Keep,
+        depth_fail_op: wgpu::StencilOperation::Keep,
+        pass_op: wgpu::StencilOperation::Replace,
+    };
+    scene
+        .clone()
+        .entry_points("vs_mark", "fs_main")
+        .depth(wgpu::CompareFunction::Always, false)
+        .stencil(wgpu::StencilState {
+            front: mark,
+            back: mark,
+            read_mask: !0,
+            write_mask: !0,
+        })
+        .color_writes(wgpu::ColorWrites::empty())
+}
+
+/// The grown instances, only where they aren't marked and on top of
+/// everything.
+pub(crate) fn outline_state(scene: &pipeline::PipelineState) -> pipeline::PipelineState {
+    let outside = wgpu::StencilFaceState {
+        compare: wgpu::CompareFunction::NotEqual,
+        fail_op: wgpu::StencilOperation::Keep,
+        depth_fail_op: wgpu::StencilOperation::Keep,
+        pass_op: wgpu::StencilOperation::Keep,
+    };
+    scene
+        .clone()
+        .entry_points("vs_outline", "fs_main")
+        .blend(Some(wgpu::BlendState::REPLACE))
+        .cull_mode(None)
+        .depth(wgpu::CompareFunction::Always, false)
+        .stencil(wgpu::StencilState {
+            front: outside,
+            back: outside,
+            read_mask: !0,
+            write_mask: 0,
+        })
+}
+
+pub(crate) fn vertex_layouts<'a>() -> [wgpu::VertexBufferLayout<'a>; 2] {
+    [ModelVertex::desc(), InstanceRaw::desc()]
+}
+
+/// Draws `outline.wgsl` around a set of selected instances.
+#[derive(Debug)]
+pub(crate) struct OutlinePass {
+    uniform: OutlineUniform,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    selected: Vec<InstanceRaw>,
+    /// `selected`, on the GPU.
+    instance_buffer: wgpu::Buffer,
+    /// Instances `instance_buffer` has room for.
+    capacity: usize,
+    pub(crate) shader: shader::Shader,
+    pub(crate) pipeline_layout: Rc<wgpu::PipelineLayout>,
+    pub(crate) mark_pipeline: Rc<wgpu::RenderPipeline>,
+    pub(crate) pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl OutlinePass {
+    pub(crate) async fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut pipeline::PipelineCache,
+        camera_layout: &wgpu::BindGroupLayout,
+        scene_state: &pipeline::PipelineState,
+        instance_count: usize,
+    ) -> Result<Self, anyhow::Error> {
+        let outline = Outline::default();
+        let uniform = OutlineUniform {
+            color: [outline.color[0], outline.color[1], outline.color[2], 1.0],
+            size: [outline.width, 1.0, 1.0, 0.0],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Instance Buffer"),
+            size: (std::mem::size_of::<InstanceRaw>() * instance_count) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("outline_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("outline_bind_group"),
+        });
+
+        let pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Outline Pipeline Layout"),
+                bind_group_layouts: &[camera_layout, &layout],
+                push_constant_ranges: &[],
+            },
+        ));
+        let shader = shader::Shader::from_file_name("Outline Shader", "outline.wgsl").await?;
+        let mark_pipeline = pipeline_cache.get_or_create(
+            device,
+            &shader,
+            &pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &vertex_layouts(),
+            &mark_state(scene_state),
+        )?;
+        let pipeline = pipeline_cache.get_or_create(
+            device,
+            &shader,
+            &pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &vertex_layouts(),
+            &outline_state(scene_state),
+        )?;
+
+        Ok(Self {
+            uniform,
+            uniform_buffer,
+            bind_group,
+            selected: Vec::new(),
+            instance_buffer,
+            capacity: instance_count,
+            shader,
+            pipeline_layout,
+            mark_pipeline,
+            pipeline,
+        })
+    }
+
+    pub(crate) fn set_outline(&mut self, queue: &wgpu::Queue, outline: Outline) {
+        let [r, g, b] = outline.color;
+        self.write_uniform(
+            queue,
+            OutlineUniform {
+                color: [r, g, b, 1.0],
+                size: [
+                    outline.width,
+                    self.uniform.size[1],
+                    self.uniform.size[2],
+                    0.0,
+                ],
+            },
+        );
+    }
+
+    pub(crate) fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.write_uniform(
+            queue,
+            OutlineUniform {
+                size: [self.uniform.size[0], width as f32, height as f32, 0.0],
+                ..self.uniform
+            },
+        );
+    }
+
+    fn write_uniform(&mut self, queue: &wgpu::Queue, uniform: OutlineUniform) {
+        if self.uniform != uniform {
+            self.uniform = uniform;
+            queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+
+    /// Make room to outline up to `instance_count` instances.
+    pub(crate) fn reserve(&mut self, device: &wgpu::Device, instance_count: usize) {
+        if self.capacity >= instance_count {
+            return;
+        }
+
+        self.instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Instance Buffer"),
+            size: (std::mem::size_of::<InstanceRaw>() * instance_count) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        self.capacity = instance_count;
+        // Written again by the next `set_selection`
+        self.selected.clear();
+    }
+
+    /// Outline these instances, again whenever they move.
+    pub(crate) fn set_selection<'a>(
+        &mut self,
+        queue: &wgpu::Queue,
+        selected: impl IntoIterator<Item = &'a Instance>,
+    ) {
+        let selected = selected
+            .into_iter()
+            .map(Instance::to_raw)
+            .collect::<Vec<_>>();
+        if self.selected == selected {
+            return;
+        }
+
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&selected));
+        self.selected = selected;
+    }
+
+    /// Last thing in the scene pass, after the blended meshes.
+    pub(crate) fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.selected.is_empty() {
+            return;
+        }
+        let instances = 0..self.selected.len() as u32;
+
+        render_pass.set_stencil_reference(SELECTED);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for pipeline in [&self.mark_pipeline, &self.pipeline] {
+            render_pass.set_pipeline(pipeline);
+            for mesh in &model.meshes {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+            }
+        }
+    }
+}
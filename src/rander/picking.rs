@@ -0,0 +1,271 @@
+use cgmath::{ElementWise, InnerSpace, Rotation, SquareMatrix, Vector3, Vector4};
+
+use super::{camera::Camera, culling::Sphere, instance::Instance, model::Model};
+
+/// A click on the canvas, in CSS pixels from its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Click {
+    /// Bump this on every click, the same click is only picked once.
+    pub(crate) generation: u32,
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+}
+
+/// What is under a canvas pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Pick {
+    /// File the model was loaded from.
+    pub(crate) model: String,
+    /// Index into the model's full detail meshes.
+    pub(crate) mesh: usize,
+    pub(crate) mesh_name: String,
+    pub(crate) instance: usize,
+    /// World space point that was hit.
+    pub(crate) position: [f32; 3],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Ray {
+    pub(crate) origin: Vector3<f32>,
+    /// Unit length in world space, so hit distances are in world units.
+    pub(crate) direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// The ray from the near plane through `pixel` of a `width` by `height`
+    /// viewport, `None` if the camera can't be inverted.
+    pub(crate) fn from_pixel(
+        camera: &Camera,
+        pixel: (f32, f32),
+        (width, height): (u32, u32),
+    ) -> Option<Ray> {
+        let inverse = camera.build_view_projection_matrix().invert()?;
+        let x = pixel.0 / width as f32 * 2.0 - 1.0;
+        let y = 1.0 - pixel.1 / height as f32 * 2.0;
+        let unproject = |z| {
+            let p = inverse * Vector4::new(x, y, z, 1.0);
+            p.truncate() / p.w
+        };
+        let (near, far) = (unproject(0.0), unproject(1.0));
+
+        Some(Ray {
+            origin: near,
+            direction: (far - near).normalize(),
+        })
+    }
+
+    /// The same ray in the model space of `instance`. Its direction is
+    /// scaled along, so distances along it still match the world ones.
+    fn to_instance_space(self, instance: &Instance) -> Ray {
+        let inverse = instance.rotation.invert();
+        Ray {
+            origin: inverse
+                .rotate_vector(self.origin - instance.position)
+                .div_element_wise(instance.scale),
+            direction: inverse
+                .rotate_vector(self.direction)
+                .div_element_wise(instance.scale),
+        }
+    }
+
+    /// Distance to where the ray enters the sphere, 0 if it starts inside.
+    pub(crate) fn intersect_sphere(&self, center: Vector3<f32>, radius: f32) -> Option<f32> {
+        let to_center = center - self.origin;
+        let along = to_center.dot(self.direction);
+        let miss2 = to_center.magnitude2() - along * along;
+        if miss2 > radius * radius {
+            return None;
+        }
+        let half_chord = (radius * radius - miss2).sqrt();
+        (along + half_chord >= 0.0).then(|| (along - half_chord).max(0.0))
+    }
+
+    /// Möller–Trumbore, both sides of the triangle count.
+    pub(crate) fn intersect_triangle(&self, [a, b, c]: [Vector3<f32>; 3]) -> Option<f32> {
+        let (edge1, edge2) = (b - a, c - a);
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let t_vec = self.origin - a;
+        let u = t_vec.dot(p) / determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = t_vec.cross(edge1);
+        let v = self.direction.dot(q) / determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) / determinant;
+        (t >= 0.0).then_some(t)
+    }
+}
+
+/// The nearest full detail triangle of any instance of `model` on `ray`.
+pub(crate) fn pick(ray: &Ray, model: &Model, instances: &[Instance]) -> Option<Pick> {
+    let meshes = model
+        .meshes
+        .iter()
+        .map(|mesh| mesh.triangles.as_slice())
+        .collect::<Vec<_>>();
+
+    nearest_hit(ray, &model.bounding_sphere(), &meshes, instances).map(|(t, mesh, instance)| Pick {
+        model: model.name.clone(),
+        mesh,
+        mesh_name: model.meshes[mesh].name.clone(),
+        instance,
+        position: (ray.origin + ray.direction * t).into(),
+    })
+}
+
+/// Distance, mesh and instance of the nearest triangle on `ray`, out of
+/// the triangles of each mesh of a model inside `bounds`. The first one
+/// wins a tie.
+fn nearest_hit(
+    ray: &Ray,
+    bounds: &Sphere,
+    meshes: &[&[[Vector3<f32>; 3]]],
+    instances: &[Instance],
+) -> Option<(f32, usize, usize)> {
+    let mut nearest: Option<(f32, usize, usize)> = None;
+
+    for (i, instance) in instances.iter().enumerate() {
+        let sphere = bounds.transformed(instance);
+        match ray.intersect_sphere(sphere.center, sphere.radius) {
+            Some(t) if nearest.map_or(true, |(best, ..)| t < best) => {}
+            _ => continue,
+        }
+
+        let local = ray.to_instance_space(instance);
+        for (m, triangles) in meshes.iter().enumerate() {
+            for triangle in triangles.iter() {
+                match local.intersect_triangle(*triangle) {
+                    Some(t) if nearest.map_or(true, |(best, ..)| t < best) => {
+                        nearest = Some((t, m, i))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    nearest
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Quaternion, Rotation3};
+
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera {
+            eye: (0.0, 0.0, 10.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+
+    /// Facing +z, around the origin.
+    fn triangle() -> [Vector3<f32>; 3] {
+        [
+            Vector3::new(-1.0, -1.0, 0.0),
+            Vector3::new(1.0, -1.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn instance(position: Vector3<f32>) -> Instance {
+        Instance {
+            position,
+            rotation: Quaternion::from_angle_y(Deg(0.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn bounds() -> Sphere {
+        crate::rander::culling::Aabb::from_points(triangle())
+            .unwrap()
+            .bounding_sphere()
+    }
+
+    #[test]
+    fn centre_pixel_looks_straight_ahead() {
+        let ray = Ray::from_pixel(&camera(), (50.0, 50.0), (100, 100)).unwrap();
+        assert!((ray.origin - Vector3::new(0.0, 0.0, 9.9)).magnitude() < 1e-4);
+        assert!((ray.direction - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+
+        let t = ray
+            .intersect_sphere(Vector3::new(0.0, 0.0, 0.0), 1.0)
+            .unwrap();
+        assert!((t - 8.9).abs() < 1e-4);
+        let t = ray.intersect_triangle(triangle()).unwrap();
+        assert!((t - 9.9).abs() < 1e-4);
+
+        // The top left corner looks up and to the left
+        let corner = Ray::from_pixel(&camera(), (0.0, 0.0), (100, 100)).unwrap();
+        assert!(corner.direction.x < 0.0 && corner.direction.y > 0.0);
+    }
+
+    #[test]
+    fn misses() {
+        let ray = Ray::from_pixel(&camera(), (50.0, 50.0), (100, 100)).unwrap();
+        assert_eq!(ray.intersect_sphere(Vector3::new(3.0, 0.0, 0.0), 1.0), None);
+        // Behind the ray
+        assert_eq!(
+            ray.intersect_sphere(Vector3::new(0.0, 0.0, 20.0), 1.0),
+            None
+        );
+        let beside = triangle().map(|corner| corner + Vector3::new(3.0, 0.0, 0.0));
+        assert_eq!(ray.intersect_triangle(beside), None);
+
+        let meshes: [&[_]; 1] = [&[triangle()]];
+        let instances = [instance(Vector3::new(0.0, 5.0, 0.0))];
+        assert_eq!(nearest_hit(&ray, &bounds(), &meshes, &instances), None);
+    }
+
+    #[test]
+    fn hits_scaled_and_rotated_instances() {
+        // Along -x at a height only the stretched triangle reaches
+        let ray = Ray {
+            origin: Vector3::new(10.0, 2.5, 0.0),
+            direction: Vector3::new(-1.0, 0.0, 0.0),
+        };
+        let meshes: [&[_]; 1] = [&[triangle()]];
+        let mut instances = [Instance {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            // Facing +x
+            rotation: Quaternion::from_angle_y(Deg(90.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }];
+        assert_eq!(nearest_hit(&ray, &bounds(), &meshes, &instances), None);
+
+        instances[0].scale = Vector3::new(1.0, 3.0, 1.0);
+        let (t, mesh, instance) = nearest_hit(&ray, &bounds(), &meshes, &instances).unwrap();
+        // In world units
+        assert!((t - 10.0).abs() < 1e-4);
+        assert_eq!((mesh, instance), (0, 0));
+    }
+
+    #[test]
+    fn nearest_hit_wins_and_ties_go_first() {
+        let ray = Ray::from_pixel(&camera(), (50.0, 50.0), (100, 100)).unwrap();
+        let meshes: [&[_]; 2] = [&[triangle()], &[triangle()]];
+        let instances = [
+            instance(Vector3::new(0.0, 0.0, -5.0)),
+            instance(Vector3::new(0.0, 0.0, 2.0)),
+            instance(Vector3::new(0.0, 0.0, 2.0)),
+        ];
+        let (t, mesh, instance) = nearest_hit(&ray, &bounds(), &meshes, &instances).unwrap();
+        assert!((t - 7.9).abs() < 1e-4);
+        assert_eq!((mesh, instance), (0, 1));
+    }
+}
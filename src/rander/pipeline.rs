@@ -0,0 +1,246 @@
+use std::{collections::HashMap, rc::Rc};
+
+use super::shader::{Shader, ShaderError};
+
+/// Everything about a render pipeline that isn't the shader, the layout or
+/// the attachment formats.
+///
+/// `PipelineState::default()` is the opaque pass: `vs_main`/`fs_main`,
+/// `REPLACE` blending, back-face culling, `Less` depth test with writes on, the
+/// stencil left alone and no multisampling.
+///
+/// ```ignore
+/// let wireframe = PipelineState::default()
+///     .polygon_mode(wgpu::PolygonMode::Line)
+///     .cull_mode(None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineState {
+    pub(crate) vertex_entry: String,
+    pub(crate) fragment_entry: String,
+    pub(crate) blend: Option<wgpu::BlendState>,
+    pub(crate) cull_mode: Option<wgpu::Face>,
+    pub(crate) polygon_mode: wgpu::PolygonMode,
+    pub(crate) topology: wgpu::PrimitiveTopology,
+    pub(crate) depth_write: bool,
+    pub(crate) depth_compare: wgpu::CompareFunction,
+    pub(crate) stencil: wgpu::StencilState,
+    pub(crate) color_writes: wgpu::ColorWrites,
+    pub(crate) sample_count: u32,
+}
+
+impl Default for PipelineState {
+    fn default() -> Self {
+        Self {
+            vertex_entry: "vs_main".to_string(),
+            fragment_entry: "fs_main".to_string(),
+            blend: Some(wgpu::BlendState::REPLACE),
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            depth_write: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            color_writes: wgpu::ColorWrites::ALL,
+            sample_count: 1,
+        }
+    }
+}
+
+impl PipelineState {
+    pub(crate) fn entry_points(mut self, vertex: &str, fragment: &str) -> Self {
+        self.vertex_entry = vertex.to_string();
+        self.fragment_entry = fragment.to_string();
+        self
+    }
+
+    /// `None` writes the fragment output as is.
+    pub(crate) fn blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub(crate) fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Anything but `Fill` requires `Features::NON_FILL_POLYGON_MODE`.
+    pub(crate) fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub(crate) fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Use `CompareFunction::Always` to turn the depth test off.
+    pub(crate) fn depth(mut self, compare: wgpu::CompareFunction, write: bool) -> Self {
+        self.depth_compare = compare;
+        self.depth_write = write;
+        self
+    }
+
+    /// The reference value is set on the render pass, with
+    /// `set_stencil_reference`.
+    pub(crate) fn stencil(mut self, stencil: wgpu::StencilState) -> Self {
+        self.stencil = stencil;
+        self
+    }
+
+    /// `ColorWrites::empty()` for passes that only touch depth or stencil.
+    pub(crate) fn color_writes(mut self, color_writes: wgpu::ColorWrites) -> Self {
+        self.color_writes = color_writes;
+        self
+    }
+
+    pub(crate) fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub(crate) fn primitive(&self) -> wgpu::PrimitiveState {
+        wgpu::PrimitiveState {
+            topology: self.topology,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: self.cull_mode,
+            polygon_mode: self.polygon_mode,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        }
+    }
+
+    pub(crate) fn depth_stencil(&self, format: wgpu::TextureFormat) -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: self.depth_write,
+            depth_compare: self.depth_compare,
+            stencil: self.stencil.clone(),
+            bias: wgpu::DepthBiasState::default(),
+        }
+    }
+
+    pub(crate) fn multisample(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        }
+    }
+}
+
+/// Identifies a render pipeline by everything that goes into creating it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    shader: u64,
+    /// Address of the `Rc` the cache keeps alive, so it can't be reused.
+    layout: usize,
+    vertex_layouts: Vec<(
+        wgpu::BufferAddress,
+        wgpu::VertexStepMode,
+        Vec<wgpu::VertexAttribute>,
+    )>,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    state: PipelineState,
+}
+
+impl PipelineKey {
+    pub(crate) fn new(
+        shader: &Shader,
+        layout: &Rc<wgpu::PipelineLayout>,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'_>],
+        state: &PipelineState,
+    ) -> Self {
+        Self {
+            shader: shader.source_hash(),
+            layout: Rc::as_ptr(layout) as usize,
+            vertex_layouts: vertex_layouts
+                .iter()
+                .map(|l| (l.array_stride, l.step_mode, l.attributes.to_vec()))
+                .collect(),
+            color_format,
+            depth_format,
+            state: state.clone(),
+        }
+    }
+}
+
+/// Render pipelines shared between everything that asks for the same one.
+///
+/// The colour format is part of the key, so a pipeline is never handed out
+/// for a different target than it was built for. Nothing is dropped when
+/// the surface format changes: it can't, `State` picks it once and resizing
+/// or recovering a lost surface reconfigures with the same one.
+#[derive(Debug, Default)]
+pub(crate) struct PipelineCache {
+    pipelines: HashMap<PipelineKey, (Rc<wgpu::PipelineLayout>, Rc<wgpu::RenderPipeline>)>,
+}
+
+impl PipelineCache {
+    pub(crate) fn get(&self, key: &PipelineKey) -> Option<Rc<wgpu::RenderPipeline>> {
+        self.pipelines
+            .get(key)
+            .map(|(_, pipeline)| pipeline.clone())
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        key: PipelineKey,
+        layout: &Rc<wgpu::PipelineLayout>,
+        pipeline: wgpu::RenderPipeline,
+    ) -> Rc<wgpu::RenderPipeline> {
+        let pipeline = Rc::new(pipeline);
+        self.pipelines
+            .insert(key, (layout.clone(), pipeline.clone()));
+        pipeline
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn get_or_create(
+        &mut self,
+        device: &wgpu::Device,
+        shader: &Shader,
+        layout: &Rc<wgpu::PipelineLayout>,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'_>],
+        state: &PipelineState,
+    ) -> Result<Rc<wgpu::RenderPipeline>, ShaderError> {
+        let key = PipelineKey::new(
+            shader,
+            layout,
+            color_format,
+            depth_format,
+            vertex_layouts,
+            state,
+        );
+        if let Some(pipeline) = self.get(&key) {
+            return Ok(pipeline);
+        }
+
+        let pipeline = shader.create_render_pipeline(
+            device,
+            layout,
+            color_format,
+            depth_format,
+            vertex_layouts,
+            state,
+        )?;
+
+        Ok(self.insert(key, layout, pipeline))
+    }
+
+    /// Drop every pipeline built from `shader`'s current source.
+    pub(crate) fn invalidate_shader(&mut self, shader: &Shader) {
+        let hash = shader.source_hash();
+        self.pipelines.retain(|key, _| key.shader != hash);
+    }
+}
@@ -0,0 +1,466 @@
+use std::rc::Rc;
+
+use wgpu::util::DeviceExt;
+
+use super::{hdr, pipeline, shader, texture};
+
+/// Built-in post-processing effects, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Effect {
+    /// Blurred bright parts added back on top, runs before tonemapping.
+    Bloom,
+    /// Runs after tonemapping, like the rest.
+    ColorGrade,
+    Vignette,
+    Fxaa,
+}
+
+impl Effect {
+    pub(crate) const ALL: [Effect; 4] = [
+        Effect::Bloom,
+        Effect::ColorGrade,
+        Effect::Vignette,
+        Effect::Fxaa,
+    ];
+
+    /// Whether the effect works on HDR colour, before tonemapping.
+    fn is_hdr(self) -> bool {
+        matches!(self, Effect::Bloom)
+    }
+}
+
+/// Two targets of the same format, one is read while the other is drawn to.
+#[derive(Debug)]
+struct PingPong {
+    format: wgpu::TextureFormat,
+    textures: [texture::Texture; 2],
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl PingPong {
+    fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let textures = [0, 1].map(|i| {
+            texture::Texture::create_render_target(
+                device,
+                config,
+                format,
+                1,
+                &format!("post_texture_{:?}_{}", format, i),
+            )
+        });
+        let bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&textures[i].view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&textures[i].sampler),
+                    },
+                ],
+                label: Some("post_input_bind_group"),
+            })
+        });
+
+        Self {
+            format,
+            textures,
+            bind_groups,
+        }
+    }
+}
+
+/// One fullscreen pass.
+///
+/// Its shader includes `common/fullscreen.wgsl`, reads the previous result
+/// from `@group(0)` and a `vec4<f32>` of parameters from `@group(1)
+/// @binding(0)`. Anything else it needs goes after the parameters.
+#[derive(Debug)]
+pub(crate) struct PostPass {
+    pub(crate) enabled: bool,
+    params: [f32; 4],
+    params_buffer: wgpu::Buffer,
+    params_layout: wgpu::BindGroupLayout,
+    params_bind_group: wgpu::BindGroup,
+    pub(crate) shader: shader::Shader,
+    pub(crate) pipeline_layout: Rc<wgpu::PipelineLayout>,
+    pub(crate) pipeline: Rc<wgpu::RenderPipeline>,
+    pub(crate) format: wgpu::TextureFormat,
+}
+
+impl PostPass {
+    #[allow(clippy::too_many_arguments)]
+    async fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut pipeline::PipelineCache,
+        input_layout: &wgpu::BindGroupLayout,
+        name: &str,
+        filename: &str,
+        format: wgpu::TextureFormat,
+        params: [f32; 4],
+        extra: &[(wgpu::BindingType, wgpu::BindingResource<'_>)],
+    ) -> Result<Self, anyhow::Error> {
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(name),
+            contents: bytemuck::cast_slice(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let entries = std::iter::once(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        })
+        .chain(extra.iter().map(|(ty, _)| *ty))
+        .enumerate()
+        .map(|(binding, ty)| wgpu::BindGroupLayoutEntry {
+            binding: binding as u32,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty,
+            count: None,
+        })
+        .collect::<Vec<_>>();
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some(name),
+        });
+        let params_bind_group = Self::create_params_bind_group(
+            device,
+            &params_layout,
+            &params_buffer,
+            extra.iter().map(|(_, resource)| resource.clone()),
+        );
+
+        let pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some(name),
+                bind_group_layouts: &[input_layout, &params_layout],
+                push_constant_ranges: &[],
+            },
+        ));
+        let shader = shader::Shader::from_file_name(name, filename).await?;
+        let pipeline = pipeline_cache.get_or_create(
+            device,
+            &shader,
+            &pipeline_layout,
+            format,
+            None,
+            &[],
+            &Self::pipeline_state(),
+        )?;
+
+        Ok(Self {
+            enabled: true,
+            params,
+            params_buffer,
+            params_layout,
+            params_bind_group,
+            shader,
+            pipeline_layout,
+            pipeline,
+            format,
+        })
+    }
+
+    /// The fullscreen triangle has no depth and isn't worth culling.
+    pub(crate) fn pipeline_state() -> pipeline::PipelineState {
+        pipeline::PipelineState::default().cull_mode(None)
+    }
+
+    fn create_params_bind_group<'a>(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        params_buffer: &'a wgpu::Buffer,
+        extra: impl Iterator<Item = wgpu::BindingResource<'a>>,
+    ) -> wgpu::BindGroup {
+        let entries = std::iter::once(params_buffer.as_entire_binding())
+            .chain(extra)
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect::<Vec<_>>();
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("post_params_bind_group"),
+        })
+    }
+
+    pub(crate) fn set_params(&mut self, queue: &wgpu::Queue, params: [f32; 4]) {
+        if self.params != params {
+            self.params = params;
+            queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&params));
+        }
+    }
+
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        input: &wgpu::BindGroup,
+        output: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, input, &[]);
+        pass.set_bind_group(1, &self.params_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// The scene is drawn into an HDR texture, then goes through the HDR
+/// effects, tonemapping and the LDR effects before landing on the surface.
+#[derive(Debug)]
+pub(crate) struct PostStack {
+    input_layout: wgpu::BindGroupLayout,
+    hdr: PingPong,
+    ldr: PingPong,
+    pub(crate) tonemap: PostPass,
+    pub(crate) effects: Vec<(Effect, PostPass)>,
+    lut: texture::Texture,
+}
+
+impl PostStack {
+    pub(crate) const LUT_SIZE: u32 = 16;
+
+    pub(crate) async fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        pipeline_cache: &mut pipeline::PipelineCache,
+    ) -> Result<Self, anyhow::Error> {
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("post_input_bind_group_layout"),
+        });
+
+        let hdr = PingPong::new(device, config, hdr::FORMAT, &input_layout);
+        let ldr = PingPong::new(device, config, config.format, &input_layout);
+        let lut = texture::Texture::create_identity_lut(device, queue, Self::LUT_SIZE);
+
+        let tonemap = PostPass::new(
+            device,
+            pipeline_cache,
+            &input_layout,
+            "Tonemap Shader",
+            "tonemap.wgsl",
+            config.format,
+            hdr::Tonemapping::Aces.params(1.0),
+            &[],
+        )
+        .await?;
+
+        let mut effects = Vec::new();
+        for (effect, filename, params) in [
+            (Effect::Bloom, "bloom.wgsl", [1.0, 0.5, 2.0, 0.0]),
+            (
+                Effect::ColorGrade,
+                "color_grade.wgsl",
+                [1.0, Self::LUT_SIZE as f32, 0.0, 0.0],
+            ),
+            (Effect::Vignette, "vignette.wgsl", [0.5, 0.6, 0.5, 0.0]),
+            (
+                Effect::Fxaa,
+                "fxaa.wgsl",
+                [8.0, 1.0 / 8.0, 1.0 / 128.0, 0.0],
+            ),
+        ] {
+            let format = if effect.is_hdr() {
+                hdr::FORMAT
+            } else {
+                config.format
+            };
+            let extra = match effect {
+                Effect::ColorGrade => Self::lut_bindings(&lut),
+                _ => Vec::new(),
+            };
+            let mut pass = PostPass::new(
+                device,
+                pipeline_cache,
+                &input_layout,
+                &format!("{:?} Shader", effect),
+                filename,
+                format,
+                params,
+                &extra,
+            )
+            .await?;
+            pass.enabled = false;
+            effects.push((effect, pass));
+        }
+
+        Ok(Self {
+            input_layout,
+            hdr,
+            ldr,
+            tonemap,
+            effects,
+            lut,
+        })
+    }
+
+    fn lut_bindings(lut: &texture::Texture) -> Vec<(wgpu::BindingType, wgpu::BindingResource<'_>)> {
+        vec![
+            (
+                wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                wgpu::BindingResource::TextureView(&lut.view),
+            ),
+            (
+                wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                wgpu::BindingResource::Sampler(&lut.sampler),
+            ),
+        ]
+    }
+
+    /// Replace the colour grading LUT, see `Texture::lut_from_image` for the
+    /// layout. `None` puts the identity LUT back.
+    pub(crate) fn set_lut(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: Option<texture::TextureImage>,
+    ) -> Result<(), anyhow::Error> {
+        let (lut, size) = match image {
+            Some(image) => texture::Texture::lut_from_image(device, queue, image)?,
+            None => (
+                texture::Texture::create_identity_lut(device, queue, Self::LUT_SIZE),
+                Self::LUT_SIZE,
+            ),
+        };
+        self.lut = lut;
+
+        let lut_bindings = Self::lut_bindings(&self.lut);
+        if let Some((_, pass)) = self
+            .effects
+            .iter_mut()
+            .find(|(effect, _)| *effect == Effect::ColorGrade)
+        {
+            pass.params_bind_group = PostPass::create_params_bind_group(
+                device,
+                &pass.params_layout,
+                &pass.params_buffer,
+                lut_bindings.into_iter().map(|(_, resource)| resource),
+            );
+            let strength = pass.params[0];
+            pass.set_params(queue, [strength, size as f32, 0.0, 0.0]);
+        }
+
+        Ok(())
+    }
+
+    /// Where the scene should be drawn, or resolved to when multisampling.
+    pub(crate) fn scene_view(&self) -> &wgpu::TextureView {
+        &self.hdr.textures[0].view
+    }
+
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.hdr = PingPong::new(device, config, self.hdr.format, &self.input_layout);
+        self.ldr = PingPong::new(device, config, self.ldr.format, &self.input_layout);
+    }
+
+    pub(crate) fn set_enabled(&mut self, effect: Effect, enabled: bool) {
+        for (e, pass) in &mut self.effects {
+            if *e == effect {
+                pass.enabled = enabled;
+            }
+        }
+    }
+
+    pub(crate) fn set_params(&mut self, queue: &wgpu::Queue, effect: Effect, params: [f32; 4]) {
+        for (e, pass) in &mut self.effects {
+            if *e == effect {
+                pass.set_params(queue, params);
+            }
+        }
+    }
+
+    /// Every pass with a shader, for hot reloading.
+    pub(crate) fn passes_mut(&mut self) -> impl Iterator<Item = &mut PostPass> {
+        std::iter::once(&mut self.tonemap).chain(self.effects.iter_mut().map(|(_, pass)| pass))
+    }
+
+    /// Run every enabled pass, the last one draws to `output`.
+    pub(crate) fn run(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let enabled = |hdr: bool| {
+            self.effects
+                .iter()
+                .filter(move |(effect, pass)| pass.enabled && effect.is_hdr() == hdr)
+                .map(|(_, pass)| pass)
+        };
+
+        let mut current = 0;
+        for pass in enabled(true) {
+            pass.draw(
+                encoder,
+                &self.hdr.bind_groups[current],
+                &self.hdr.textures[1 - current].view,
+            );
+            current = 1 - current;
+        }
+
+        let ldr_passes = enabled(false).collect::<Vec<_>>();
+        if ldr_passes.is_empty() {
+            self.tonemap
+                .draw(encoder, &self.hdr.bind_groups[current], output);
+            return;
+        }
+
+        self.tonemap.draw(
+            encoder,
+            &self.hdr.bind_groups[current],
+            &self.ldr.textures[0].view,
+        );
+        current = 0;
+        for (i, pass) in ldr_passes.iter().enumerate() {
+            let target = if i + 1 == ldr_passes.len() {
+                output
+            } else {
+                &self.ldr.textures[1 - current].view
+            };
+            pass.draw(encoder, &self.ldr.bind_groups[current], target);
+            current = 1 - current;
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::Element;
+
+/// What WebGL2 devices are guaranteed to take, until the renderer knows
+/// better.
+pub(crate) const DEFAULT_MAX_SIZE: u32 = 2048;
+
+/// Backing store size for a canvas `css_size` CSS pixels large: one pixel
+/// per device pixel, up to `max_pixel_ratio` per CSS pixel, and scaled
+/// down to fit within `max_size` on both sides.
+pub(crate) fn backing_size(
+    css_size: (i32, i32),
+    device_pixel_ratio: f64,
+    max_pixel_ratio: f64,
+    max_size: u32,
+) -> (u32, u32) {
+    let ratio = device_pixel_ratio.min(max_pixel_ratio);
+    let (width, height) = (
+        css_size.0.max(0) as f64 * ratio,
+        css_size.1.max(0) as f64 * ratio,
+    );
+    let scale = (max_size as f64 / width.max(height)).min(1.0);
+    (
+        ((width * scale).round() as u32).max(1),
+        ((height * scale).round() as u32).max(1),
+    )
+}
+
+#[wasm_bindgen]
+extern "C" {
+    // `web_sys` only has it behind `web_sys_unstable_apis`
+    type ResizeObserver;
+
+    #[wasm_bindgen(constructor)]
+    fn new(callback: &JsValue) -> ResizeObserver;
+
+    #[wasm_bindgen(method)]
+    fn observe(this: &ResizeObserver, target: &Element);
+
+    #[wasm_bindgen(method)]
+    fn disconnect(this: &ResizeObserver);
+}
+
+/// Calls `on_resize` once `element` is laid out and again whenever its size
+/// changes, until dropped.
+pub(crate) struct SizeObserver {
+    observer: ResizeObserver,
+    _on_resize: Closure<dyn FnMut()>,
+}
+
+impl SizeObserver {
+    pub(crate) fn new(element: &Element, on_resize: impl FnMut() + 'static) -> Self {
+        let on_resize = Closure::wrap(Box::new(on_resize) as Box<dyn FnMut()>);
+        let observer = ResizeObserver::new(on_resize.as_ref().unchecked_ref());
+        observer.observe(element);
+        Self {
+            observer,
+            _on_resize: on_resize,
+        }
+    }
+}
+
+impl Drop for SizeObserver {
+    fn drop(&mut self) {
+        self.observer.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_device_pixel_ratio() {
+        assert_eq!(backing_size((400, 300), 1.0, 2.0, 4096), (400, 300));
+        assert_eq!(backing_size((400, 300), 2.0, 2.0, 4096), (800, 600));
+        assert_eq!(backing_size((401, 301), 1.5, 2.0, 4096), (602, 452));
+    }
+
+    #[test]
+    fn caps_the_pixel_ratio() {
+        assert_eq!(backing_size((400, 300), 3.0, 2.0, 4096), (800, 600));
+        assert_eq!(backing_size((400, 300), 2.0, 0.5, 4096), (200, 150));
+    }
+
+    #[test]
+    fn fits_within_the_max_size() {
+        assert_eq!(backing_size((1920, 1080), 2.0, 2.0, 2048), (2048, 1152));
+        assert_eq!(backing_size((100, 3000), 1.0, 2.0, 2048), (68, 2048));
+    }
+
+    #[test]
+    fn never_collapses() {
+        assert_eq!(backing_size((0, 0), 2.0, 2.0, 2048), (1, 1));
+        assert_eq!(backing_size((-5, 10), 1.0, 2.0, 2048), (1, 10));
+    }
+}
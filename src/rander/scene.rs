@@ -0,0 +1,378 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    animation::{Easing, Property, Repeat, Target},
+    camera::Camera,
+    instance::Instance,
+    light::LightUniform,
+};
+use crate::resources::Resources;
+
+/// Everything the renderer draws, as read from and written to a scene file
+/// like `static/scene.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Scene {
+    pub(crate) models: Vec<SceneModel>,
+    /// Rigged glTF models, drawn alongside `models`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) skinned_models: Vec<SceneSkinnedModel>,
+    pub(crate) camera: SceneCamera,
+    #[serde(default)]
+    pub(crate) lights: Vec<SceneLight>,
+    /// Equirectangular `.hdr` under `static/` for the skybox and image-based
+    /// lighting.
+    #[serde(default)]
+    pub(crate) environment: Option<String>,
+    /// Keyframed instances, light and camera, see `animation::Animation`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) animations: Vec<SceneTrack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SceneModel {
+    /// `.obj` under `static/`.
+    pub(crate) file: String,
+    /// Levels of detail from fine to coarse, generated when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) lods: Vec<String>,
+    pub(crate) instances: Vec<SceneInstance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SceneSkinnedModel {
+    /// `.glb` under `static/`, only its first skin is used.
+    pub(crate) file: String,
+    /// Animation to play, the first one when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) clip: Option<String>,
+    #[serde(default = "yes")]
+    pub(crate) looping: bool,
+    pub(crate) instances: Vec<SceneInstance>,
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SceneInstance {
+    pub(crate) position: [f32; 3],
+    /// Quaternion as `[x, y, z, w]`.
+    #[serde(default = "identity")]
+    pub(crate) rotation: [f32; 4],
+    #[serde(default = "one")]
+    pub(crate) scale: [f32; 3],
+}
+
+fn identity() -> [f32; 4] {
+    [0.0, 0.0, 0.0, 1.0]
+}
+
+fn one() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+impl From<&SceneInstance> for Instance {
+    fn from(instance: &SceneInstance) -> Self {
+        Instance {
+            position: instance.position.into(),
+            rotation: instance.rotation.into(),
+            scale: instance.scale.into(),
+        }
+    }
+}
+
+impl From<&Instance> for SceneInstance {
+    fn from(instance: &Instance) -> Self {
+        let rotation = instance.rotation;
+        SceneInstance {
+            position: instance.position.into(),
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            scale: instance.scale.into(),
+        }
+    }
+}
+
+/// The aspect ratio comes from the canvas instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SceneCamera {
+    pub(crate) eye: [f32; 3],
+    pub(crate) target: [f32; 3],
+    pub(crate) up: [f32; 3],
+    /// In degrees.
+    pub(crate) fovy: f32,
+    pub(crate) znear: f32,
+    pub(crate) zfar: f32,
+}
+
+impl SceneCamera {
+    pub(crate) fn to_camera(&self, aspect: f32) -> Camera {
+        Camera {
+            eye: self.eye.into(),
+            target: self.target.into(),
+            up: self.up.into(),
+            aspect,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+}
+
+impl From<&Camera> for SceneCamera {
+    fn from(camera: &Camera) -> Self {
+        SceneCamera {
+            eye: camera.eye.into(),
+            target: camera.target.into(),
+            up: camera.up.into(),
+            fovy: camera.fovy,
+            znear: camera.znear,
+            zfar: camera.zfar,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SceneLight {
+    pub(crate) position: [f32; 3],
+    /// Linear.
+    pub(crate) color: [f32; 3],
+}
+
+impl From<&SceneLight> for LightUniform {
+    fn from(light: &SceneLight) -> Self {
+        LightUniform {
+            position: light.position,
+            _padding: 0,
+            color: light.color,
+            _padding2: 0,
+        }
+    }
+}
+
+impl From<&LightUniform> for SceneLight {
+    fn from(light: &LightUniform) -> Self {
+        SceneLight {
+            position: light.position,
+            color: light.color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SceneTrack {
+    pub(crate) target: Target,
+    pub(crate) property: Property,
+    pub(crate) keyframes: Vec<SceneKeyframe>,
+    #[serde(default)]
+    pub(crate) easing: Easing,
+    #[serde(default)]
+    pub(crate) repeat: Repeat,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SceneKeyframe {
+    /// In seconds, increasing along the track.
+    pub(crate) time: f32,
+    /// `[x, y, z]` for positions, scales and colours, `[x, y, z, w]` for
+    /// rotations and `[degrees]` for the field of view.
+    pub(crate) value: Vec<f32>,
+}
+
+impl Scene {
+    pub(crate) async fn from_file_name(file_name: &str) -> Result<Self, anyhow::Error> {
+        let json = Resources::from_path(&format!("/static/{}", file_name))
+            .request_string()
+            .await?;
+        Self::from_json(&json).map_err(|err| anyhow::anyhow!("{}: {}", file_name, err))
+    }
+
+    pub(crate) fn from_json(json: &str) -> Result<Self, anyhow::Error> {
+        let scene: Scene = serde_json::from_str(json)?;
+        let instances = scene
+            .models
+            .iter()
+            .flat_map(|model| &model.instances)
+            .chain(
+                scene
+                    .skinned_models
+                    .iter()
+                    .flat_map(|model| &model.instances),
+            );
+        for instance in instances {
+            check_scale(&instance.scale)?;
+        }
+        let scale_keyframes = scene
+            .animations
+            .iter()
+            .filter(|track| track.property == Property::Scale)
+            .flat_map(|track| &track.keyframes);
+        for keyframe in scale_keyframes {
+            check_scale(&keyframe.value)?;
+        }
+        Ok(scene)
+    }
+
+    pub(crate) fn to_json(&self) -> Result<String, anyhow::Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// A scale of 0 can't be inverted for the normal matrix, and neither can
+/// one that isn't a number.
+fn check_scale(scale: &[f32]) -> Result<(), anyhow::Error> {
+    if scale.iter().any(|s| !s.is_finite() || *s <= 0.0) {
+        anyhow::bail!("scale {:?} isn't positive", scale);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> Scene {
+        Scene {
+            models: vec![SceneModel {
+                file: "cube.obj".to_string(),
+                lods: vec!["cube-low.obj".to_string()],
+                instances: vec![
+                    SceneInstance {
+                        position: [1.0, -2.5, 3.0],
+                        rotation: [0.0, 0.38268343, 0.0, 0.9238795],
+                        scale: [1.0, 2.0, 0.5],
+                    },
+                    SceneInstance {
+                        position: [0.0, 0.0, 0.0],
+                        rotation: identity(),
+                        scale: one(),
+                    },
+                ],
+            }],
+            skinned_models: vec![SceneSkinnedModel {
+                file: "column.glb".to_string(),
+                clip: Some("Sway".to_string()),
+                looping: false,
+                instances: vec![SceneInstance {
+                    position: [0.0, 2.0, 0.0],
+                    rotation: identity(),
+                    scale: one(),
+                }],
+            }],
+            camera: SceneCamera {
+                eye: [0.0, 0.0, 2.0],
+                target: [0.0, 0.0, 0.0],
+                up: [0.0, 1.0, 0.0],
+                fovy: 45.0,
+                znear: 0.1,
+                zfar: 100.0,
+            },
+            lights: vec![SceneLight {
+                position: [2.0, 2.0, 2.0],
+                color: [1.0, 1.0, 1.0],
+            }],
+            environment: Some("environment.hdr".to_string()),
+            animations: vec![SceneTrack {
+                target: Target::Instance(1),
+                property: Property::Rotation,
+                keyframes: vec![
+                    SceneKeyframe {
+                        time: 0.0,
+                        value: identity().to_vec(),
+                    },
+                    SceneKeyframe {
+                        time: 1.5,
+                        value: vec![0.0, 0.70710677, 0.0, 0.70710677],
+                    },
+                ],
+                easing: Easing::EaseInOut,
+                repeat: Repeat::PingPong,
+            }],
+        }
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let scene = scene();
+        let json = scene.to_json().unwrap();
+        assert_eq!(Scene::from_json(&json).unwrap(), scene);
+    }
+
+    #[test]
+    fn round_trips_through_instances_and_camera() {
+        let scene = scene();
+        for instance in &scene.models[0].instances {
+            assert_eq!(&SceneInstance::from(&Instance::from(instance)), instance);
+        }
+        let camera = scene.camera.to_camera(16.0 / 9.0);
+        assert_eq!(SceneCamera::from(&camera), scene.camera);
+        let light = LightUniform::from(&scene.lights[0]);
+        assert_eq!(SceneLight::from(&light), scene.lights[0]);
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let scene = Scene::from_json(
+            r#"{
+                "models": [{ "file": "cube.obj", "instances": [{ "position": [1, 2, 3] }] }],
+                "camera": {
+                    "eye": [0, 0, 2], "target": [0, 0, 0], "up": [0, 1, 0],
+                    "fovy": 45, "znear": 0.1, "zfar": 100
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(scene.lights.is_empty());
+        assert!(scene.skinned_models.is_empty());
+        assert_eq!(scene.environment, None);
+        assert!(scene.animations.is_empty());
+        assert!(scene.models[0].lods.is_empty());
+        assert_eq!(
+            scene.models[0].instances[0],
+            SceneInstance {
+                position: [1.0, 2.0, 3.0],
+                rotation: identity(),
+                scale: one(),
+            }
+        );
+    }
+
+    #[test]
+    fn default_scene_round_trips() {
+        let json = include_str!("../../static/scene.json");
+        let scene = Scene::from_json(json).unwrap();
+        assert_eq!(scene.models.len(), 1);
+        assert_eq!(scene.models[0].instances.len(), 100);
+        assert_eq!(Scene::from_json(&scene.to_json().unwrap()).unwrap(), scene);
+    }
+
+    #[test]
+    fn rejects_unknown_shapes() {
+        assert!(Scene::from_json(r#"{ "models": [] }"#).is_err());
+        assert!(Scene::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn rejects_flat_scales() {
+        let scene = |scale: &str| {
+            Scene::from_json(&format!(
+                r#"{{
+                    "models": [{{
+                        "file": "cube.obj",
+                        "instances": [{{ "position": [0, 0, 0], "scale": {} }}]
+                    }}],
+                    "camera": {{
+                        "eye": [0, 1, 2], "target": [0, 0, 0], "up": [0, 1, 0],
+                        "fovy": 45, "znear": 0.1, "zfar": 100
+                    }}
+                }}"#,
+                scale
+            ))
+        };
+        assert!(scene("[1, 2, 0.5]").is_ok());
+        assert!(scene("[1, 0, 1]").is_err());
+        assert!(scene("[-1, 1, 1]").is_err());
+        // serde_json has no literal for infinity, but overflows to it
+        assert!(scene("[1e39, 1, 1]").is_err());
+    }
+}
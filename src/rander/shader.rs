@@ -1,23 +1,298 @@
+pub(crate) mod preprocessor;
+
+use std::fmt;
+
+use super::pipeline::PipelineState;
+
+#[derive(Debug)]
 pub(crate) struct Shader {
     lable: String,
+    filename: String,
+    defines: Vec<String>,
+    /// Source after preprocessing.
     code: String,
+    /// Where each line of `code` came from, see `preprocessor::Expanded`.
+    lines: Vec<(String, u32)>,
+    module: naga::Module,
+}
+
+/// A WGSL error with the place it points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ShaderError {
+    pub(crate) filename: String,
+    pub(crate) message: String,
+    /// 1-based (line, column), if the error has a span.
+    pub(crate) location: Option<(u32, u32)>,
+}
+
+impl ShaderError {
+    /// An error at `location` in the preprocessed source of `filename`,
+    /// moved back to the file and line it came from.
+    fn new(
+        lines: &[(String, u32)],
+        filename: &str,
+        message: String,
+        location: Option<naga::SourceLocation>,
+    ) -> Self {
+        match location {
+            Some(location) => {
+                let (filename, line, column) = locate(lines, filename, location);
+                Self {
+                    filename,
+                    message,
+                    location: Some((line, column)),
+                }
+            }
+            None => Self {
+                filename: filename.to_string(),
+                message,
+                location: None,
+            },
+        }
+    }
+}
+
+/// File, line and column of `location` in the preprocessed source of
+/// `filename`, see `preprocessor::Expanded::lines`.
+fn locate(
+    lines: &[(String, u32)],
+    filename: &str,
+    location: naga::SourceLocation,
+) -> (String, u32, u32) {
+    let line = (location.line_number as usize).checked_sub(1);
+    match line.and_then(|line| lines.get(line)) {
+        Some((filename, line)) => (filename.clone(), *line, location.line_position),
+        None => (
+            filename.to_string(),
+            location.line_number,
+            location.line_position,
+        ),
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some((line, column)) => {
+                write!(f, "{}:{}:{}: {}", self.filename, line, column, self.message)
+            }
+            None => write!(f, "{}: {}", self.filename, self.message),
+        }
+    }
 }
 
+impl std::error::Error for ShaderError {}
+
 impl Shader {
     pub(crate) async fn from_file_name<'a>(
         name: &'a str,
         filename: &str,
     ) -> Result<Shader, anyhow::Error> {
-        let code = crate::resources::Resources::from_path(&format!("static/shader/{}", filename))
-            .request_string()
-            .await?;
+        Self::from_file_name_with_defines(name, filename, &[]).await
+    }
+
+    /// Load `filename` with `defines` switched on for its `#ifdef`s.
+    pub(crate) async fn from_file_name_with_defines(
+        name: &str,
+        filename: &str,
+        defines: &[&str],
+    ) -> Result<Shader, anyhow::Error> {
+        let source = Self::request_code(filename, defines).await?;
+
+        Ok(Self::from_code(name, filename, defines, source)?)
+    }
+
+    /// Parse and validate already preprocessed `source` with naga, nothing
+    /// touches the GPU here.
+    pub(crate) fn from_code(
+        name: &str,
+        filename: &str,
+        defines: &[&str],
+        source: preprocessor::Expanded,
+    ) -> Result<Shader, ShaderError> {
+        let preprocessor::Expanded { code, lines } = source;
+        // Where a span is in the files the source came from
+        let at = |span: naga::Span| {
+            let (filename, line, column) = locate(&lines, filename, span.location(&code));
+            format!("{}:{}:{}", filename, line, column)
+        };
+
+        let module = naga::front::wgsl::parse_str(&code).map_err(|err| {
+            let mut message = err.message().to_string();
+            for (span, label) in err.labels() {
+                message += &format!("\n  --> {}: {}", at(span.into()), label);
+            }
+
+            ShaderError::new(&lines, filename, message, err.location(&code))
+        })?;
+
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .map_err(|err| {
+            let mut message = err.as_inner().to_string();
+            let mut source = std::error::Error::source(err.as_inner());
+            while let Some(inner) = source {
+                message += &format!(": {}", inner);
+                source = inner.source();
+            }
+            for (span, label) in err.spans() {
+                message += &format!("\n  --> {}: {}", at(*span), label);
+            }
+
+            ShaderError::new(&lines, filename, message, err.location(&code))
+        })?;
 
         Ok(Self {
             lable: name.to_string(),
+            filename: filename.to_string(),
+            defines: defines.iter().map(|d| d.to_string()).collect(),
             code,
+            lines,
+            module,
         })
     }
 
+    /// Hash of the preprocessed source, changes whenever the shader does.
+    pub(crate) fn source_hash(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.code.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    async fn request_code(
+        filename: &str,
+        defines: &[&str],
+    ) -> Result<preprocessor::Expanded, anyhow::Error> {
+        let mut preprocessor = preprocessor::Preprocessor::new(defines);
+        preprocessor.fetch(filename).await?;
+
+        Ok(preprocessor.run(filename)?)
+    }
+
+    /// Fetch the source again, returns `Ok(None)` if nothing changed.
+    pub(crate) async fn refetch(&self) -> Result<Option<Shader>, anyhow::Error> {
+        let defines = self.defines.iter().map(String::as_str).collect::<Vec<_>>();
+        let source = Self::request_code(&self.filename, &defines).await?;
+
+        if source.code == self.code {
+            return Ok(None);
+        }
+
+        Ok(Some(Self::from_code(
+            &self.lable,
+            &self.filename,
+            &defines,
+            source,
+        )?))
+    }
+
+    /// Check that every `@location` input of the vertex entry point is
+    /// fed by one of `vertex_layouts` with a matching scalar kind.
+    pub(crate) fn check_vertex_layouts(
+        &self,
+        entry_point: &str,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'_>],
+    ) -> Result<(), ShaderError> {
+        let entry = self
+            .module
+            .entry_points
+            .iter()
+            .find(|ep| ep.stage == naga::ShaderStage::Vertex && ep.name == entry_point)
+            .ok_or_else(|| {
+                ShaderError::new(
+                    &self.lines,
+                    &self.filename,
+                    format!("no vertex entry point named `{}`", entry_point),
+                    None,
+                )
+            })?;
+
+        let mut inputs = Vec::new();
+        for arg in &entry.function.arguments {
+            match (&arg.binding, &self.module.types[arg.ty].inner) {
+                (Some(naga::Binding::Location { location, .. }), _) => {
+                    inputs.push((*location, arg.ty))
+                }
+                (None, naga::TypeInner::Struct { members, .. }) => {
+                    inputs.extend(members.iter().filter_map(|m| match m.binding {
+                        Some(naga::Binding::Location { location, .. }) => Some((location, m.ty)),
+                        _ => None,
+                    }))
+                }
+                _ => {}
+            }
+        }
+
+        for (location, ty) in inputs {
+            let span = self.module.types.get_span(ty).location(&self.code);
+            let attribute = vertex_layouts
+                .iter()
+                .flat_map(|layout| layout.attributes.iter())
+                .find(|a| a.shader_location == location)
+                .ok_or_else(|| {
+                    ShaderError::new(
+                        &self.lines,
+                        &self.filename,
+                        format!(
+                            "`{}` input @location({}) is not provided by any vertex buffer layout",
+                            entry_point, location
+                        ),
+                        Some(span),
+                    )
+                })?;
+
+            let kind = match self.module.types[ty].inner {
+                naga::TypeInner::Scalar { kind, .. } | naga::TypeInner::Vector { kind, .. } => kind,
+                _ => continue,
+            };
+            if kind != vertex_format_kind(attribute.format) {
+                return Err(ShaderError::new(
+                    &self.lines,
+                    &self.filename,
+                    format!(
+                        "`{}` input @location({}) is {:?}, but the vertex buffer layout gives {:?}",
+                        entry_point, location, kind, attribute.format
+                    ),
+                    Some(span),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Same as `create_render_pipeline`, but catch validation errors
+    /// instead of letting them reach the uncaptured error handler.
+    pub(crate) async fn try_create_render_pipeline(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'_>],
+        state: &PipelineState,
+    ) -> Result<wgpu::RenderPipeline, anyhow::Error> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = self.create_render_pipeline(
+            device,
+            layout,
+            color_format,
+            depth_format,
+            vertex_layouts,
+            state,
+        );
+
+        match device.pop_error_scope().await {
+            Some(err) => Err(anyhow::anyhow!("{}: {}", self.filename, err)),
+            None => Ok(pipeline?),
+        }
+    }
+
     pub(crate) fn create_render_pipeline(
         &self,
         device: &wgpu::Device,
@@ -25,57 +300,219 @@ impl Shader {
         color_format: wgpu::TextureFormat,
         depth_format: Option<wgpu::TextureFormat>,
         vertex_layouts: &[wgpu::VertexBufferLayout],
-    ) -> wgpu::RenderPipeline {
+        state: &PipelineState,
+    ) -> Result<wgpu::RenderPipeline, ShaderError> {
+        self.check_vertex_layouts(&state.vertex_entry, vertex_layouts)?;
+
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some(&self.lable),
             source: wgpu::ShaderSource::Wgsl(self.code.clone().into()),
         });
 
-        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: vertex_layouts,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: color_format,
-                    blend: Some(wgpu::BlendState {
-                        alpha: wgpu::BlendComponent::REPLACE,
-                        color: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
+        Ok(
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Render Pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: &state.vertex_entry,
+                    buffers: vertex_layouts,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: &state.fragment_entry,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: state.blend,
+                        write_mask: state.color_writes,
+                    })],
+                }),
+                primitive: state.primitive(),
+                depth_stencil: depth_format.map(|format| state.depth_stencil(format)),
+                multisample: state.multisample(),
+                multiview: None,
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-                format,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
+        )
+    }
+
+    pub(crate) fn create_compute_pipeline(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        entry_point: &str,
+    ) -> Result<wgpu::ComputePipeline, ShaderError> {
+        if !self
+            .module
+            .entry_points
+            .iter()
+            .any(|ep| ep.stage == naga::ShaderStage::Compute && ep.name == entry_point)
+        {
+            return Err(ShaderError::new(
+                &self.lines,
+                &self.filename,
+                format!("no compute entry point named `{}`", entry_point),
+                None,
+            ));
+        }
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&self.lable),
+            source: wgpu::ShaderSource::Wgsl(self.code.clone().into()),
+        });
+
+        Ok(
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Compute Pipeline"),
+                layout: Some(layout),
+                module: &shader,
+                entry_point,
             }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        })
+        )
+    }
+}
+
+/// The scalar kind a vertex shader sees for an attribute of `format`.
+fn vertex_format_kind(format: wgpu::VertexFormat) -> naga::ScalarKind {
+    use wgpu::VertexFormat as F;
+    match format {
+        F::Uint8x2 | F::Uint8x4 | F::Uint16x2 | F::Uint16x4 => naga::ScalarKind::Uint,
+        F::Uint32 | F::Uint32x2 | F::Uint32x3 | F::Uint32x4 => naga::ScalarKind::Uint,
+        F::Sint8x2 | F::Sint8x4 | F::Sint16x2 | F::Sint16x4 => naga::ScalarKind::Sint,
+        F::Sint32 | F::Sint32x2 | F::Sint32x3 | F::Sint32x4 => naga::ScalarKind::Sint,
+        _ => naga::ScalarKind::Float,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rander::{
+        gizmo::GizmoVertex,
+        instance::InstanceRaw,
+        model::{ModelVertex, SkinnedVertex, Vertex},
+    };
+
+    fn load(filename: &str, defines: &[&str]) -> Result<Shader, anyhow::Error> {
+        let mut preprocessor = preprocessor::Preprocessor::new(defines);
+        preprocessor.read(filename)?;
+        let code = preprocessor.run(filename)?;
+
+        Ok(Shader::from_code(filename, filename, defines, code)?)
+    }
+
+    #[test]
+    fn shaders_match_their_vertex_layouts() {
+        let model = [ModelVertex::desc(), InstanceRaw::desc()];
+        let skinned = [SkinnedVertex::desc(), InstanceRaw::desc()];
+        let light = [ModelVertex::desc()];
+        let gizmo = [GizmoVertex::desc()];
+        let fullscreen: [wgpu::VertexBufferLayout<'_>; 0] = [];
+
+        // What each pipeline is built from, see where the shaders are loaded
+        let pipelines: &[(&str, &[&str], &str, &[wgpu::VertexBufferLayout<'_>])] = &[
+            ("shader.wgsl", &["NORMAL_MAP"], "vs_main", &model),
+            ("shader.wgsl", &["NORMAL_MAP", "IBL"], "vs_main", &model),
+            (
+                "shader.wgsl",
+                &["NORMAL_MAP", "SKINNED"],
+                "vs_main",
+                &skinned,
+            ),
+            (
+                "shader.wgsl",
+                &["NORMAL_MAP", "IBL", "SKINNED"],
+                "vs_main",
+                &skinned,
+            ),
+            ("light.wgsl", &[], "vs_main", &light),
+            ("outline.wgsl", &[], "vs_mark", &model),
+            ("outline.wgsl", &[], "vs_outline", &model),
+            ("gizmo.wgsl", &[], "vs_main", &gizmo),
+            ("gradient.wgsl", &[], "vs_main", &fullscreen),
+            ("skybox.wgsl", &[], "vs_main", &fullscreen),
+            ("bloom.wgsl", &[], "vs_main", &fullscreen),
+            ("color_grade.wgsl", &[], "vs_main", &fullscreen),
+            ("fxaa.wgsl", &[], "vs_main", &fullscreen),
+            ("tonemap.wgsl", &[], "vs_main", &fullscreen),
+            ("vignette.wgsl", &[], "vs_main", &fullscreen),
+            ("environment/equirect.wgsl", &[], "vs_main", &fullscreen),
+            ("environment/irradiance.wgsl", &[], "vs_main", &fullscreen),
+            ("environment/prefilter.wgsl", &[], "vs_main", &fullscreen),
+            ("environment/brdf.wgsl", &[], "vs_main", &fullscreen),
+        ];
+        for (filename, defines, entry_point, layouts) in pipelines {
+            let shader = load(filename, defines)
+                .unwrap_or_else(|err| panic!("{} {:?}: {}", filename, defines, err));
+            if let Err(err) = shader.check_vertex_layouts(entry_point, layouts) {
+                panic!("{} {:?}: {}", filename, defines, err);
+            }
+        }
+
+        load("culling.wgsl", &[]).unwrap();
+    }
+
+    #[test]
+    fn every_shader_validates() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/static/shader");
+        // `common/` is only ever included
+        for subdir in ["", "environment"] {
+            for entry in std::fs::read_dir(format!("{}/{}", dir, subdir)).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().map_or(false, |e| e == "wgsl") {
+                    let filename = path.strip_prefix(dir).unwrap().to_str().unwrap();
+                    let filename = filename.trim_start_matches('/');
+                    load(filename, &["NORMAL_MAP"])
+                        .unwrap_or_else(|err| panic!("{}: {}", filename, err));
+                }
+            }
+        }
+    }
+
+    /// `sources` preprocessed from the first one and built.
+    fn build(sources: &[(&str, &str)], defines: &[&str]) -> Result<Shader, ShaderError> {
+        let mut preprocessor = preprocessor::Preprocessor::new(defines);
+        for (filename, code) in sources {
+            preprocessor.insert(filename.to_string(), code.to_string());
+        }
+        let filename = sources[0].0;
+        let source = preprocessor.run(filename)?;
+
+        Shader::from_code(filename, filename, defines, source)
+    }
+
+    #[test]
+    fn errors_point_at_the_source() {
+        let err = build(&[("broken.wgsl", "fn main() {\n    let x = ;\n}\n")], &[]).unwrap_err();
+        assert_eq!(err.filename, "broken.wgsl");
+        assert_eq!(err.location, Some((2, 13)));
+        assert!(err.to_string().starts_with("broken.wgsl:2:13: "));
+    }
+
+    #[test]
+    fn errors_point_past_includes_and_branches() {
+        let sources = [
+            (
+                "broken.wgsl",
+                "#include \"common.wgsl\"\n\
+                 #ifdef FOO\n\
+                 fn foo() {}\n\
+                 #else\n\
+                 fn main() {\n    let x = ;\n}\n\
+                 #endif\n",
+            ),
+            ("common.wgsl", "fn one() -> f32 {\n    return 1.0;\n}\n"),
+        ];
+        let err = build(&sources, &[]).unwrap_err();
+        assert_eq!(err.filename, "broken.wgsl");
+        assert_eq!(err.location, Some((6, 13)));
+
+        // And into the included file
+        let sources = [
+            ("broken.wgsl", "\n#include \"common.wgsl\"\n"),
+            ("common.wgsl", "\nfn one() -> f32 {\n    return 1.0 +;\n}\n"),
+        ];
+        let err = build(&sources, &[]).unwrap_err();
+        assert_eq!(err.filename, "common.wgsl");
+        assert_eq!(err.location.map(|(line, _)| line), Some(3));
     }
 }
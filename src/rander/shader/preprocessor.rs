@@ -0,0 +1,410 @@
+//! A small preprocessor run over WGSL before naga sees it.
+//!
+//! Directives have to sit on their own line:
+//! - `#include "common/camera.wgsl"`, resolved against `static/shader/`.
+//!   A file is only pasted the first time it is included.
+//! - `#define NAME` or `#define NAME value`, a value replaces every
+//!   whole-word `NAME` after it.
+//! - `#ifdef NAME`, `#ifndef NAME`, `#else`, `#endif`.
+//!
+//! Directives and the lines of branches not taken turn into blank lines,
+//! and `Expanded::lines` says which file and line each line came from.
+
+use std::collections::{HashMap, HashSet};
+
+use super::ShaderError;
+use crate::resources::Resources;
+
+#[derive(Debug, Default)]
+pub(crate) struct Preprocessor {
+    defines: HashMap<String, String>,
+    sources: HashMap<String, String>,
+}
+
+/// Preprocessed WGSL.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Expanded {
+    pub(crate) code: String,
+    /// File and 1-based line in it of each line of `code`.
+    pub(crate) lines: Vec<(String, u32)>,
+}
+
+impl Expanded {
+    /// Finish the line of `code` that is line `n` (0-based) of `filename`.
+    fn end_line(&mut self, filename: &str, n: usize) {
+        self.code.push('\n');
+        self.lines.push((filename.to_string(), n as u32 + 1));
+    }
+}
+
+/// One open `#ifdef`/`#ifndef`.
+struct Branch {
+    /// Whether the enclosing branch is emitting.
+    parent: bool,
+    /// Whether this branch is emitting.
+    taken: bool,
+    seen_else: bool,
+}
+
+enum Directive<'a> {
+    Include(&'a str),
+    Define(&'a str, &'a str),
+    IfDef(&'a str),
+    IfNDef(&'a str),
+    Else,
+    EndIf,
+}
+
+impl Preprocessor {
+    pub(crate) fn new(defines: &[&str]) -> Self {
+        Self {
+            defines: defines
+                .iter()
+                .map(|name| (name.to_string(), String::new()))
+                .collect(),
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Fetch `filename` and every file it includes, directly or not.
+    pub(crate) async fn fetch(&mut self, filename: &str) -> Result<(), anyhow::Error> {
+        let mut pending = vec![filename.to_string()];
+
+        while let Some(filename) = pending.pop() {
+            if self.sources.contains_key(&filename) {
+                continue;
+            }
+
+            let code = Resources::from_path(&format!("static/shader/{}", filename))
+                .request_string()
+                .await?;
+            pending.extend(self.insert(filename, code));
+        }
+
+        Ok(())
+    }
+
+    /// `fetch` from `static/shader/` on disk instead, for native tests.
+    #[cfg(test)]
+    pub(crate) fn read(&mut self, filename: &str) -> Result<(), anyhow::Error> {
+        let mut pending = vec![filename.to_string()];
+
+        while let Some(filename) = pending.pop() {
+            if self.sources.contains_key(&filename) {
+                continue;
+            }
+
+            let path = format!("{}/static/shader/{}", env!("CARGO_MANIFEST_DIR"), filename);
+            let code = std::fs::read_to_string(&path)
+                .map_err(|err| anyhow::anyhow!("{}: {}", path, err))?;
+            pending.extend(self.insert(filename, code));
+        }
+
+        Ok(())
+    }
+
+    /// Keep `code` as the source of `filename`, returns the files it
+    /// includes.
+    pub(super) fn insert(&mut self, filename: String, code: String) -> Vec<String> {
+        let includes = code
+            .lines()
+            .filter_map(|line| match parse_directive(line) {
+                Some(Ok(Directive::Include(path))) => Some(path.to_string()),
+                _ => None,
+            })
+            .collect();
+        self.sources.insert(filename, code);
+        includes
+    }
+
+    /// Expand `filename` into plain WGSL.
+    pub(crate) fn run(&self, filename: &str) -> Result<Expanded, ShaderError> {
+        let mut defines = self.defines.clone();
+        let mut included = HashSet::new();
+        let mut out = Expanded::default();
+
+        self.expand(filename, &mut defines, &mut vec![], &mut included, &mut out)?;
+
+        Ok(out)
+    }
+
+    fn expand(
+        &self,
+        filename: &str,
+        defines: &mut HashMap<String, String>,
+        stack: &mut Vec<String>,
+        included: &mut HashSet<String>,
+        out: &mut Expanded,
+    ) -> Result<(), ShaderError> {
+        let error = |line: usize, message: String| ShaderError {
+            filename: filename.to_string(),
+            message,
+            location: Some((line as u32 + 1, 1)),
+        };
+
+        let code = self.sources.get(filename).ok_or_else(|| ShaderError {
+            filename: filename.to_string(),
+            message: "source was never fetched".to_string(),
+            location: None,
+        })?;
+
+        stack.push(filename.to_string());
+        included.insert(filename.to_string());
+
+        let mut branches: Vec<Branch> = Vec::new();
+        for (n, line) in code.lines().enumerate() {
+            let active = branches.last().map_or(true, |b| b.parent && b.taken);
+
+            let directive = match parse_directive(line) {
+                Some(directive) => directive.map_err(|message| error(n, message))?,
+                None => {
+                    if active {
+                        substitute(line, defines, &mut out.code);
+                    }
+                    out.end_line(filename, n);
+                    continue;
+                }
+            };
+            out.end_line(filename, n);
+
+            match directive {
+                Directive::IfDef(name) | Directive::IfNDef(name) => {
+                    let defined = defines.contains_key(name);
+                    branches.push(Branch {
+                        parent: active,
+                        taken: defined == matches!(directive, Directive::IfDef(_)),
+                        seen_else: false,
+                    });
+                }
+                Directive::Else => match branches.last_mut() {
+                    Some(branch) if !branch.seen_else => {
+                        branch.taken = !branch.taken;
+                        branch.seen_else = true;
+                    }
+                    Some(_) => return Err(error(n, "duplicate `#else`".to_string())),
+                    None => return Err(error(n, "`#else` without `#ifdef`".to_string())),
+                },
+                Directive::EndIf => {
+                    if branches.pop().is_none() {
+                        return Err(error(n, "`#endif` without `#ifdef`".to_string()));
+                    }
+                }
+                _ if !active => {}
+                Directive::Define(name, value) => {
+                    defines.insert(name.to_string(), value.to_string());
+                }
+                Directive::Include(path) => {
+                    if let Some(start) = stack.iter().position(|f| f == path) {
+                        let mut cycle = stack[start..].to_vec();
+                        cycle.push(path.to_string());
+                        return Err(error(n, format!("include cycle: {}", cycle.join(" -> "))));
+                    }
+                    if !included.contains(path) {
+                        self.expand(path, defines, stack, included, out)?;
+                    }
+                }
+            }
+        }
+
+        if !branches.is_empty() {
+            return Err(error(code.lines().count(), "missing `#endif`".to_string()));
+        }
+
+        stack.pop();
+
+        Ok(())
+    }
+}
+
+/// `None` for ordinary lines, `Some(Err(..))` for malformed directives.
+fn parse_directive(line: &str) -> Option<Result<Directive<'_>, String>> {
+    let line = line.trim().strip_prefix('#')?;
+    let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+
+    let name = || {
+        if rest.is_empty() || !rest.chars().all(is_ident_char) {
+            Err(format!("`#{}` expects a name, found `{}`", keyword, rest))
+        } else {
+            Ok(rest)
+        }
+    };
+
+    Some(match keyword {
+        "include" => rest
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .map(Directive::Include)
+            .ok_or_else(|| format!("`#include` expects a quoted path, found `{}`", rest)),
+        "define" => {
+            let (name, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if name.is_empty() || !name.chars().all(is_ident_char) {
+                Err(format!("`#define` expects a name, found `{}`", name))
+            } else {
+                Ok(Directive::Define(name, value.trim()))
+            }
+        }
+        "ifdef" => name().map(Directive::IfDef),
+        "ifndef" => name().map(Directive::IfNDef),
+        "else" => Ok(Directive::Else),
+        "endif" => Ok(Directive::EndIf),
+        _ => Err(format!("unknown directive `#{}`", keyword)),
+    })
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Push `line` to `out`, replacing defined names that carry a value.
+fn substitute(line: &str, defines: &HashMap<String, String>, out: &mut String) {
+    let mut word = String::new();
+    for c in line.chars().chain(std::iter::once('\n')) {
+        if is_ident_char(c) {
+            word.push(c);
+            continue;
+        }
+
+        match defines.get(&word) {
+            Some(value) if !value.is_empty() => out.push_str(value),
+            _ => out.push_str(&word),
+        }
+        word.clear();
+
+        if c != '\n' {
+            out.push(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocessor(defines: &[&str], sources: &[(&str, &str)]) -> Preprocessor {
+        let mut preprocessor = Preprocessor::new(defines);
+        for (filename, code) in sources {
+            preprocessor.insert(filename.to_string(), code.to_string());
+        }
+        preprocessor
+    }
+
+    /// The lines of `expanded` that aren't blank.
+    fn text(expanded: &Expanded) -> Vec<&str> {
+        expanded
+            .code
+            .lines()
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn includes_once() {
+        let preprocessor = preprocessor(
+            &[],
+            &[
+                (
+                    "main.wgsl",
+                    "#include \"a.wgsl\"\n#include \"a.wgsl\"\nmain",
+                ),
+                ("a.wgsl", "a"),
+            ],
+        );
+        assert_eq!(text(&preprocessor.run("main.wgsl").unwrap()), ["a", "main"]);
+    }
+
+    #[test]
+    fn maps_lines_back_to_their_files() {
+        let preprocessor = preprocessor(
+            &[],
+            &[
+                (
+                    "main.wgsl",
+                    "#include \"a.wgsl\"\n#ifdef FOO\nfoo\n#endif\nmain",
+                ),
+                ("a.wgsl", "a\nb"),
+            ],
+        );
+        let expanded = preprocessor.run("main.wgsl").unwrap();
+        assert_eq!(expanded.code, "\na\nb\n\n\n\nmain\n");
+        let line = |file: &str, line| (file.to_string(), line);
+        assert_eq!(
+            expanded.lines,
+            [
+                line("main.wgsl", 1),
+                line("a.wgsl", 1),
+                line("a.wgsl", 2),
+                line("main.wgsl", 2),
+                line("main.wgsl", 3),
+                line("main.wgsl", 4),
+                line("main.wgsl", 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_include_cycles() {
+        let preprocessor = preprocessor(
+            &[],
+            &[
+                ("a.wgsl", "#include \"b.wgsl\""),
+                ("b.wgsl", "\n#include \"a.wgsl\""),
+            ],
+        );
+        let err = preprocessor.run("a.wgsl").unwrap_err();
+        assert_eq!(err.filename, "b.wgsl");
+        assert_eq!(err.location, Some((2, 1)));
+        assert_eq!(err.message, "include cycle: a.wgsl -> b.wgsl -> a.wgsl");
+    }
+
+    #[test]
+    fn reports_missing_includes() {
+        let preprocessor = preprocessor(&[], &[("a.wgsl", "#include \"missing.wgsl\"")]);
+        let err = preprocessor.run("a.wgsl").unwrap_err();
+        assert_eq!(err.filename, "missing.wgsl");
+        assert_eq!(err.message, "source was never fetched");
+    }
+
+    #[test]
+    fn picks_branches_by_define() {
+        let sources = [(
+            "a.wgsl",
+            "#ifdef FOO\nfoo\n#else\nnot foo\n#endif\n#ifndef FOO\nno foo\n#endif",
+        )];
+        assert_eq!(
+            text(&preprocessor(&["FOO"], &sources).run("a.wgsl").unwrap()),
+            ["foo"]
+        );
+        assert_eq!(
+            text(&preprocessor(&[], &sources).run("a.wgsl").unwrap()),
+            ["not foo", "no foo"]
+        );
+    }
+
+    #[test]
+    fn substitutes_define_values() {
+        let preprocessor = preprocessor(
+            &[],
+            &[("a.wgsl", "#define COUNT 4u\nlet n = COUNT + COUNTS;")],
+        );
+        assert_eq!(
+            preprocessor.run("a.wgsl").unwrap().code,
+            "\nlet n = 4u + COUNTS;\n"
+        );
+    }
+
+    #[test]
+    fn reports_unbalanced_branches() {
+        let err = preprocessor(&[], &[("a.wgsl", "#ifdef FOO\nfoo")])
+            .run("a.wgsl")
+            .unwrap_err();
+        assert_eq!(err.message, "missing `#endif`");
+        assert_eq!(err.location, Some((3, 1)));
+
+        let err = preprocessor(&[], &[("a.wgsl", "foo\n#endif")])
+            .run("a.wgsl")
+            .unwrap_err();
+        assert_eq!(err.message, "`#endif` without `#ifdef`");
+        assert_eq!(err.location, Some((2, 1)));
+    }
+}
@@ -0,0 +1,270 @@
+use cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3};
+
+use super::keyframes::Keyframes;
+
+/// Local transform of a node, relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Transform {
+    pub(crate) translation: Vector3<f32>,
+    pub(crate) rotation: Quaternion<f32>,
+    pub(crate) scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub(crate) fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Node {
+    pub(crate) name: String,
+    /// Always before this node in `Skeleton::nodes`.
+    pub(crate) parent: Option<usize>,
+    /// Where the node is when no clip moves it.
+    pub(crate) rest: Transform,
+}
+
+/// A node the skinned vertices are weighted to.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Joint {
+    pub(crate) node: usize,
+    /// Takes model space into the joint's space at bind time.
+    pub(crate) inverse_bind: Matrix4<f32>,
+}
+
+/// A joint hierarchy, along with the nodes above the joints that move
+/// them too.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Skeleton {
+    pub(crate) nodes: Vec<Node>,
+    /// In the order the vertex joint indices refer to them.
+    pub(crate) joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub(crate) fn rest_pose(&self) -> Vec<Transform> {
+        self.nodes.iter().map(|node| node.rest).collect()
+    }
+
+    /// Model space transform of every node in `pose`.
+    pub(crate) fn global_transforms(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let mut globals = Vec::<Matrix4<f32>>::with_capacity(self.nodes.len());
+        for (node, local) in self.nodes.iter().zip(pose) {
+            let parent = node.parent.map_or(Matrix4::identity(), |p| globals[p]);
+            globals.push(parent * local.matrix());
+        }
+        globals
+    }
+
+    /// What the `SKINNED` path of `shader.wgsl` multiplies vertices by per
+    /// joint, identity for every joint when a model bound at rest is at rest.
+    pub(crate) fn joint_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let globals = self.global_transforms(pose);
+        self.joints
+            .iter()
+            .map(|joint| globals[joint.node] * joint.inverse_bind)
+            .collect()
+    }
+}
+
+/// Which part of a node's transform a channel animates.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Track {
+    Translation(Keyframes<Vector3<f32>>),
+    Rotation(Keyframes<Quaternion<f32>>),
+    Scale(Keyframes<Vector3<f32>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Channel {
+    /// Index into `Skeleton::nodes`.
+    pub(crate) node: usize,
+    pub(crate) track: Track,
+}
+
+/// A sampled animation of a skeleton.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Clip {
+    pub(crate) name: String,
+    pub(crate) channels: Vec<Channel>,
+    /// Time of the last keyframe of any channel.
+    pub(crate) duration: f32,
+}
+
+impl Clip {
+    pub(crate) fn new(name: String, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .map(|channel| match &channel.track {
+                Track::Translation(keyframes) | Track::Scale(keyframes) => keyframes.duration(),
+                Track::Rotation(keyframes) => keyframes.duration(),
+            })
+            .fold(0.0, f32::max);
+        Self {
+            name,
+            channels,
+            duration,
+        }
+    }
+
+    /// Time into the clip `time` seconds after it started, wrapping
+    /// around when `looping` and holding the last frame otherwise.
+    pub(crate) fn local_time(&self, time: f32, looping: bool) -> f32 {
+        if looping && self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            time.clamp(0.0, self.duration)
+        }
+    }
+
+    /// Moves the animated nodes of `pose` to where they are at `time`,
+    /// leaving the others alone.
+    pub(crate) fn sample(&self, time: f32, looping: bool, pose: &mut [Transform]) {
+        let time = self.local_time(time, looping);
+        for channel in &self.channels {
+            let transform = &mut pose[channel.node];
+            match &channel.track {
+                Track::Translation(keyframes) => transform.translation = keyframes.sample(time),
+                Track::Rotation(keyframes) => transform.rotation = keyframes.sample(time),
+                Track::Scale(keyframes) => transform.scale = keyframes.sample(time),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, EuclideanSpace, InnerSpace, Rotation3, Transform as _};
+
+    use super::*;
+    use crate::rander::keyframes::Interpolation;
+
+    /// A root at the origin and a child one unit up, bound where they rest.
+    fn arm() -> Skeleton {
+        let child_rest = Transform {
+            translation: Vector3::new(0.0, 1.0, 0.0),
+            ..Transform::default()
+        };
+        Skeleton {
+            nodes: vec![
+                Node {
+                    name: "root".to_string(),
+                    parent: None,
+                    rest: Transform::default(),
+                },
+                Node {
+                    name: "child".to_string(),
+                    parent: Some(0),
+                    rest: child_rest,
+                },
+            ],
+            joints: vec![
+                Joint {
+                    node: 0,
+                    inverse_bind: Matrix4::identity(),
+                },
+                Joint {
+                    node: 1,
+                    inverse_bind: child_rest.matrix().invert().unwrap(),
+                },
+            ],
+        }
+    }
+
+    fn bend() -> Clip {
+        let axis = Vector3::unit_z();
+        Clip::new(
+            "bend".to_string(),
+            vec![Channel {
+                node: 1,
+                track: Track::Rotation(
+                    Keyframes::new(
+                        vec![0.0, 1.0, 2.0],
+                        vec![
+                            Quaternion::from_axis_angle(axis, Deg(0.0)),
+                            Quaternion::from_axis_angle(axis, Deg(90.0)),
+                            Quaternion::from_axis_angle(axis, Deg(0.0)),
+                        ],
+                        Interpolation::Linear,
+                    )
+                    .unwrap(),
+                ),
+            }],
+        )
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn rest_pose_is_the_bind_pose() {
+        let skeleton = arm();
+        for matrix in skeleton.joint_matrices(&skeleton.rest_pose()) {
+            assert!(matrix.is_identity());
+        }
+    }
+
+    #[test]
+    fn children_follow_their_parents() {
+        let skeleton = arm();
+        let mut pose = skeleton.rest_pose();
+        pose[0].translation = Vector3::new(2.0, 0.0, 0.0);
+
+        let globals = skeleton.global_transforms(&pose);
+        assert_near(
+            globals[1].transform_point([0.0; 3].into()).to_vec(),
+            Vector3::new(2.0, 1.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn clip_bends_the_child() {
+        let skeleton = arm();
+        let clip = bend();
+        assert_eq!(clip.duration, 2.0);
+
+        let mut pose = skeleton.rest_pose();
+        clip.sample(1.0, false, &mut pose);
+        let matrices = skeleton.joint_matrices(&pose);
+
+        // A vertex at the tip of the arm, weighted fully to the child
+        let tip = matrices[1].transform_point([0.0, 2.0, 0.0].into());
+        assert_near(tip.to_vec(), Vector3::new(-1.0, 1.0, 0.0));
+        // The translation the clip doesn't touch stays at rest
+        assert_eq!(pose[1].translation, Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn loops_or_holds_past_the_end() {
+        let clip = bend();
+        assert_eq!(clip.local_time(2.5, true), 0.5);
+        assert_eq!(clip.local_time(-0.5, true), 1.5);
+        assert_eq!(clip.local_time(2.5, false), 2.0);
+
+        let (mut looped, mut held) = (vec![Transform::default(); 2], vec![Transform::default(); 2]);
+        clip.sample(3.0, true, &mut looped);
+        clip.sample(3.0, false, &mut held);
+        assert!((looped[1].rotation - bend_at(1.0)).magnitude() < 1e-5);
+        assert!((held[1].rotation - bend_at(2.0)).magnitude() < 1e-5);
+    }
+
+    fn bend_at(time: f32) -> Quaternion<f32> {
+        let mut pose = vec![Transform::default(); 2];
+        bend().sample(time, false, &mut pose);
+        pose[1].rotation
+    }
+}
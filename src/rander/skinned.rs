@@ -0,0 +1,692 @@
+use std::{collections::HashMap, rc::Rc};
+
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use super::{
+    hdr,
+    instance::{Instance, InstanceRaw},
+    keyframes::{Interpolate, Interpolation, Keyframes},
+    model::{self, AlphaMode, DrawModel, Material, Mesh, ModelVertex, SkinnedVertex, Vertex},
+    pipeline, scene, shader,
+    skeleton::{Channel, Clip, Joint, Node, Skeleton, Track, Transform},
+    texture::{self, TextureImage},
+};
+use crate::resources::Resources;
+
+/// Joints `shader.wgsl` has room for, see `joints` in it.
+pub(crate) const MAX_JOINTS: usize = 128;
+
+/// Normal map texel pointing straight out of the surface.
+const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+#[derive(Debug, Clone)]
+pub(crate) struct SkinnedMeshData {
+    pub(crate) name: String,
+    pub(crate) vertices: Vec<SkinnedVertex>,
+    pub(crate) indices: Vec<u32>,
+    /// Index into the glTF materials, one past them for the default one.
+    pub(crate) material: usize,
+}
+
+/// Everything in a glTF file's first skin but the textures, before any of
+/// it is on the GPU.
+#[derive(Debug, Clone)]
+pub(crate) struct SkinData {
+    pub(crate) meshes: Vec<SkinnedMeshData>,
+    pub(crate) skeleton: Skeleton,
+    pub(crate) clips: Vec<Clip>,
+}
+
+impl SkinData {
+    /// `buffers` holds the data of each of the document's buffers.
+    pub(crate) fn from_gltf(
+        document: &gltf::Document,
+        buffers: &[Vec<u8>],
+    ) -> Result<Self, anyhow::Error> {
+        let get_buffer = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(Vec::as_slice);
+        let skin = document
+            .skins()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("no skin to animate"))?;
+        if skin.joints().count() > MAX_JOINTS {
+            anyhow::bail!(
+                "{} joints, only {} are supported",
+                skin.joints().count(),
+                MAX_JOINTS
+            );
+        }
+
+        let (skeleton, node_indices) = Self::skeleton(document, &skin, get_buffer)?;
+
+        let mut meshes = Vec::new();
+        for node in document.nodes() {
+            let mesh = match (node.mesh(), node.skin()) {
+                (Some(mesh), Some(node_skin)) if node_skin.index() == skin.index() => mesh,
+                _ => continue,
+            };
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let (vertices, indices) = Self::primitive(&primitive, get_buffer)?;
+                meshes.push(SkinnedMeshData {
+                    name: format!("{}/{}", mesh.name().unwrap_or("mesh"), primitive.index()),
+                    vertices,
+                    indices,
+                    material: primitive
+                        .material()
+                        .index()
+                        .unwrap_or_else(|| document.materials().len()),
+                });
+            }
+        }
+
+        let mut clips = Vec::new();
+        for animation in document.animations() {
+            let mut channels = Vec::new();
+            for channel in animation.channels() {
+                let node = match node_indices.get(&channel.target().node().index()) {
+                    Some(&node) => node,
+                    // Nothing to do with the skeleton
+                    None => continue,
+                };
+                if let Some(track) = Self::track(&channel, get_buffer)? {
+                    channels.push(Channel { node, track });
+                }
+            }
+            let name = animation
+                .name()
+                .map_or_else(|| format!("clip {}", animation.index()), str::to_string);
+            clips.push(Clip::new(name, channels));
+        }
+
+        Ok(Self {
+            meshes,
+            skeleton,
+            clips,
+        })
+    }
+
+    /// The joints of `skin` and every node above them, along with where
+    /// each glTF node ended up in it.
+    fn skeleton<'s>(
+        document: &gltf::Document,
+        skin: &gltf::Skin,
+        get_buffer: impl Clone + Fn(gltf::Buffer) -> Option<&'s [u8]>,
+    ) -> Result<(Skeleton, HashMap<usize, usize>), anyhow::Error> {
+        let mut parents = vec![None; document.nodes().len()];
+        for node in document.nodes() {
+            for child in node.children() {
+                parents[child.index()] = Some(node.index());
+            }
+        }
+
+        let mut needed = vec![false; parents.len()];
+        for joint in skin.joints() {
+            let mut node = Some(joint.index());
+            while let Some(index) = node {
+                needed[index] = true;
+                node = parents[index];
+            }
+        }
+
+        // Depth first from the roots, so parents come before their children
+        let mut nodes = Vec::new();
+        let mut node_indices = HashMap::new();
+        let mut stack = document
+            .nodes()
+            .filter(|node| parents[node.index()].is_none())
+            .map(|node| (node, None))
+            .collect::<Vec<_>>();
+        while let Some((node, parent)) = stack.pop() {
+            if !needed[node.index()] {
+                continue;
+            }
+            let (translation, rotation, scale) = node.transform().decomposed();
+            node_indices.insert(node.index(), nodes.len());
+            nodes.push(Node {
+                name: node.name().unwrap_or_default().to_string(),
+                parent,
+                rest: Transform {
+                    translation: translation.into(),
+                    rotation: rotation.into(),
+                    scale: scale.into(),
+                },
+            });
+            let index = nodes.len() - 1;
+            stack.extend(node.children().map(|child| (child, Some(index))));
+        }
+
+        let inverse_binds = skin
+            .reader(get_buffer)
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(Matrix4::from).collect::<Vec<_>>());
+        let joints = skin
+            .joints()
+            .enumerate()
+            .map(|(i, joint)| Joint {
+                node: node_indices[&joint.index()],
+                inverse_bind: inverse_binds
+                    .as_ref()
+                    .and_then(|matrices| matrices.get(i).copied())
+                    .unwrap_or_else(Matrix4::identity),
+            })
+            .collect();
+
+        Ok((Skeleton { nodes, joints }, node_indices))
+    }
+
+    fn primitive<'s>(
+        primitive: &gltf::Primitive,
+        get_buffer: impl Clone + Fn(gltf::Buffer) -> Option<&'s [u8]>,
+    ) -> Result<(Vec<SkinnedVertex>, Vec<u32>), anyhow::Error> {
+        let reader = primitive.reader(get_buffer);
+        let missing = |attribute| anyhow::anyhow!("primitive without {}", attribute);
+
+        let positions = reader
+            .read_positions()
+            .ok_or_else(|| missing("positions"))?
+            .collect::<Vec<_>>();
+        let normals = reader
+            .read_normals()
+            .ok_or_else(|| missing("normals"))?
+            .collect::<Vec<_>>();
+        let tex_coords = match reader.read_tex_coords(0) {
+            Some(tex_coords) => tex_coords.into_f32().collect(),
+            None => vec![[0.0; 2]; positions.len()],
+        };
+        let joints = reader
+            .read_joints(0)
+            .ok_or_else(|| missing("joints"))?
+            .into_u16()
+            .map(|joints| joints.map(u32::from))
+            .collect::<Vec<_>>();
+        let weights = reader
+            .read_weights(0)
+            .ok_or_else(|| missing("weights"))?
+            .into_f32()
+            .collect::<Vec<_>>();
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect::<Vec<_>>(),
+        };
+        if [normals.len(), tex_coords.len(), joints.len(), weights.len()]
+            .iter()
+            .any(|&len| len != positions.len())
+        {
+            anyhow::bail!("primitive attributes have different lengths");
+        }
+
+        let mut vertices = positions
+            .iter()
+            .zip(&normals)
+            .zip(&tex_coords)
+            .map(|((&position, &normal), &tex_coords)| ModelVertex {
+                position,
+                tex_coords,
+                normal,
+                tangent: [0.0; 3],
+                bitangent: [0.0; 3],
+            })
+            .collect::<Vec<_>>();
+        model::compute_tangents(&mut vertices, &indices);
+
+        let vertices = vertices
+            .into_iter()
+            .zip(joints)
+            .zip(weights)
+            .map(|((vertex, joints), weights)| {
+                let total = weights.iter().sum::<f32>();
+                let weights = if total > 0.0 {
+                    weights.map(|w| w / total)
+                } else {
+                    [1.0, 0.0, 0.0, 0.0]
+                };
+                SkinnedVertex::new(vertex, joints, weights)
+            })
+            .collect();
+
+        Ok((vertices, indices))
+    }
+
+    /// `None` for morph target weights, which aren't supported.
+    fn track<'s>(
+        channel: &gltf::animation::Channel,
+        get_buffer: impl Clone + Fn(gltf::Buffer) -> Option<&'s [u8]>,
+    ) -> Result<Option<Track>, anyhow::Error> {
+        use gltf::animation::{util::ReadOutputs, Interpolation as GltfInterpolation};
+
+        let reader = channel.reader(get_buffer);
+        let times = reader
+            .read_inputs()
+            .ok_or_else(|| anyhow::anyhow!("channel without keyframe times"))?
+            .collect::<Vec<_>>();
+        let interpolation = channel.sampler().interpolation();
+        let outputs = reader
+            .read_outputs()
+            .ok_or_else(|| anyhow::anyhow!("channel without keyframe values"))?;
+
+        fn keyframes<T: Interpolate>(
+            times: Vec<f32>,
+            values: impl Iterator<Item = T>,
+            interpolation: GltfInterpolation,
+        ) -> Result<Keyframes<T>, anyhow::Error> {
+            let (interpolation, values) = match interpolation {
+                GltfInterpolation::Step => (Interpolation::Step, values.collect()),
+                GltfInterpolation::Linear => (Interpolation::Linear, values.collect()),
+                // Only the values, between their in and out tangents
+                GltfInterpolation::CubicSpline => {
+                    (Interpolation::Linear, values.skip(1).step_by(3).collect())
+                }
+            };
+            Keyframes::new(times, values, interpolation)
+                .ok_or_else(|| anyhow::anyhow!("channel keyframes don't pair up"))
+        }
+
+        Ok(match outputs {
+            ReadOutputs::Translations(translations) => Some(Track::Translation(keyframes(
+                times,
+                translations.map(Into::into),
+                interpolation,
+            )?)),
+            ReadOutputs::Rotations(rotations) => Some(Track::Rotation(keyframes(
+                times,
+                rotations.into_f32().map(Into::into),
+                interpolation,
+            )?)),
+            ReadOutputs::Scales(scales) => Some(Track::Scale(keyframes(
+                times,
+                scales.map(Into::into),
+                interpolation,
+            )?)),
+            ReadOutputs::MorphTargetWeights(_) => None,
+        })
+    }
+}
+
+/// Data of every buffer of `gltf`, from the `.glb` itself or the files
+/// next to it under `static/`.
+async fn load_buffers(gltf: &gltf::Gltf) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        buffers.push(match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("no binary chunk for buffer {}", buffer.index()))?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                anyhow::bail!("embedded buffers aren't supported, use a .glb instead")
+            }
+            gltf::buffer::Source::Uri(uri) => {
+                Resources::from_path(&format!("/static/{}", uri))
+                    .request_binary()
+                    .await?
+            }
+        });
+    }
+    Ok(buffers)
+}
+
+async fn load_image(
+    texture: gltf::Texture<'_>,
+    buffers: &[Vec<u8>],
+) -> Result<TextureImage, anyhow::Error> {
+    Ok(match texture.source().source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = &buffers[view.buffer().index()];
+            TextureImage::from_bytes(&buffer[view.offset()..view.offset() + view.length()])?
+        }
+        gltf::image::Source::Uri { uri, .. } => TextureImage::from_file_name(uri).await?,
+    })
+}
+
+/// The base colour factor only counts without a base colour texture.
+async fn load_material(
+    material: &gltf::Material<'_>,
+    buffers: &[Vec<u8>],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> Result<Material, anyhow::Error> {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, a] = pbr.base_color_factor();
+    let diffuse = match pbr.base_color_texture() {
+        Some(info) => load_image(info.texture(), buffers).await?,
+        // Linear factor into the sRGB texture
+        None => TextureImage::from_color(
+            [r, g, b, 1.0].map(|c| (c.powf(1.0 / 2.2) * 255.0).round() as u8),
+        ),
+    };
+    let normal = match material.normal_texture() {
+        Some(normal) => load_image(normal.texture(), buffers).await?,
+        None => TextureImage::from_color(FLAT_NORMAL),
+    };
+    let alpha_mode = match material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+        gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+        gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+    };
+
+    Material::new(
+        material.name().unwrap_or("material"),
+        alpha_mode,
+        a,
+        diffuse,
+        normal,
+        device,
+        queue,
+        layout,
+    )
+}
+
+/// A glTF model moved by its skeleton, every instance in the same pose.
+#[derive(Debug)]
+pub(crate) struct SkinnedModel {
+    /// File the model was loaded from.
+    pub(crate) name: String,
+    pub(crate) meshes: Vec<Mesh>,
+    pub(crate) materials: Vec<Material>,
+    pub(crate) skeleton: Skeleton,
+    pub(crate) clips: Vec<Clip>,
+    /// Index into `clips` of the one playing, `None` holds the rest pose.
+    clip: Option<usize>,
+    looping: bool,
+    joint_buffer: wgpu::Buffer,
+    /// The light and the joint matrices, in place of the light bind group.
+    bind_group: wgpu::BindGroup,
+    instance_buffer: wgpu::Buffer,
+    instance_count: u32,
+}
+
+impl SkinnedModel {
+    /// `skin_layout` is `SkinnedPass::layout`, `light_buffer` the scene's
+    /// light.
+    pub(crate) async fn new(
+        desc: &scene::SceneSkinnedModel,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_layout: &wgpu::BindGroupLayout,
+        skin_layout: &wgpu::BindGroupLayout,
+        light_buffer: &wgpu::Buffer,
+    ) -> Result<Self, anyhow::Error> {
+        let name = &desc.file;
+        let bytes = Resources::from_path(&format!("/static/{}", name))
+            .request_binary()
+            .await?;
+        let gltf = gltf::Gltf::from_slice(&bytes)?;
+        let buffers = load_buffers(&gltf).await?;
+        let data = SkinData::from_gltf(&gltf.document, &buffers)
+            .map_err(|err| anyhow::anyhow!("{}: {}", name, err))?;
+
+        let mut materials = Vec::new();
+        for material in gltf.document.materials() {
+            materials
+                .push(load_material(&material, &buffers, device, queue, texture_layout).await?);
+        }
+        // glTF's default material, for primitives without one
+        materials.push(Material::new(
+            "default",
+            AlphaMode::Opaque,
+            1.0,
+            TextureImage::from_color([255; 4]),
+            TextureImage::from_color(FLAT_NORMAL),
+            device,
+            queue,
+            texture_layout,
+        )?);
+
+        let meshes = data
+            .meshes
+            .iter()
+            .map(|mesh| {
+                Mesh::from_vertices(
+                    &mesh.name,
+                    &mesh.vertices,
+                    &mesh.indices,
+                    mesh.material,
+                    device,
+                )
+            })
+            .collect();
+
+        let clip = match &desc.clip {
+            Some(clip) => Some(
+                data.clips
+                    .iter()
+                    .position(|c| &c.name == clip)
+                    .ok_or_else(|| anyhow::anyhow!("{}: no clip named {:?}", name, clip))?,
+            ),
+            None => (!data.clips.is_empty()).then_some(0),
+        };
+
+        let joint_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{:?} Joint Buffer", name)),
+            size: (std::mem::size_of::<[[f32; 4]; 4]>() * MAX_JOINTS) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: skin_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: joint_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(&format!("{:?} Skin Bind Group", name)),
+        });
+
+        let instances = desc
+            .instances
+            .iter()
+            .map(|instance| Instance::from(instance).to_raw())
+            .collect::<Vec<_>>();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Instance Buffer", name)),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let model = Self {
+            name: name.to_string(),
+            meshes,
+            materials,
+            skeleton: data.skeleton,
+            clips: data.clips,
+            clip,
+            looping: desc.looping,
+            joint_buffer,
+            bind_group,
+            instance_buffer,
+            instance_count: instances.len() as u32,
+        };
+        model.update(queue, 0.0);
+        Ok(model)
+    }
+
+    /// Pose the skeleton `time` seconds into the clip.
+    pub(crate) fn update(&self, queue: &wgpu::Queue, time: f32) {
+        let mut pose = self.skeleton.rest_pose();
+        if let Some(clip) = self.clip {
+            self.clips[clip].sample(time, self.looping, &mut pose);
+        }
+        let matrices = self
+            .skeleton
+            .joint_matrices(&pose)
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(&matrices));
+    }
+}
+
+pub(crate) fn vertex_layouts<'a>() -> [wgpu::VertexBufferLayout<'a>; 2] {
+    [SkinnedVertex::desc(), InstanceRaw::desc()]
+}
+
+/// Draws `SkinnedModel`s with the `SKINNED` path of `shader.wgsl`.
+#[derive(Debug)]
+pub(crate) struct SkinnedPass {
+    /// The light uniform and a skinned model's joint matrices.
+    pub(crate) layout: wgpu::BindGroupLayout,
+    pub(crate) shader: shader::Shader,
+    pub(crate) pipeline_layout: Rc<wgpu::PipelineLayout>,
+    pub(crate) pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+impl SkinnedPass {
+    /// `defines` and `ibl_layout` are the same as for the unskinned
+    /// models.
+    pub(crate) async fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut pipeline::PipelineCache,
+        texture_layout: &wgpu::BindGroupLayout,
+        camera_layout: &wgpu::BindGroupLayout,
+        ibl_layout: Option<&wgpu::BindGroupLayout>,
+        defines: &[&str],
+        scene_state: &pipeline::PipelineState,
+    ) -> Result<Self, anyhow::Error> {
+        let uniform = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT),
+                uniform(1, wgpu::ShaderStages::VERTEX),
+            ],
+            label: Some("skin_bind_group_layout"),
+        });
+
+        let mut bind_group_layouts = vec![texture_layout, camera_layout, &layout];
+        bind_group_layouts.extend(ibl_layout);
+        let pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Skinned Pipeline Layout"),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            },
+        ));
+
+        let defines = defines
+            .iter()
+            .copied()
+            .chain(["SKINNED"])
+            .collect::<Vec<_>>();
+        let shader =
+            shader::Shader::from_file_name_with_defines("Skinned Shader", "shader.wgsl", &defines)
+                .await?;
+        let pipeline = pipeline_cache.get_or_create(
+            device,
+            &shader,
+            &pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &vertex_layouts(),
+            scene_state,
+        )?;
+
+        Ok(Self {
+            layout,
+            shader,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    /// With the opaque meshes, blended materials aren't sorted.
+    pub(crate) fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        models: &'a [SkinnedModel],
+        camera_bind_group: &'a wgpu::BindGroup,
+        ibl_bind_group: Option<&'a wgpu::BindGroup>,
+    ) {
+        if models.is_empty() {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        if let Some(ibl_bind_group) = ibl_bind_group {
+            render_pass.set_bind_group(3, ibl_bind_group, &[]);
+        }
+        for model in models {
+            render_pass.set_vertex_buffer(1, model.instance_buffer.slice(..));
+            for mesh in &model.meshes {
+                render_pass.draw_mesh_instanced(
+                    mesh,
+                    &model.materials[mesh.material],
+                    0..model.instance_count,
+                    camera_bind_group,
+                    &model.bind_group,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column() -> SkinData {
+        let gltf = gltf::Gltf::from_slice(include_bytes!("../../static/column.glb")).unwrap();
+        let buffers = vec![gltf.blob.clone().unwrap()];
+        SkinData::from_gltf(&gltf.document, &buffers).unwrap()
+    }
+
+    #[test]
+    fn loads_the_skeleton() {
+        let data = column();
+        let names = data
+            .skeleton
+            .nodes
+            .iter()
+            .map(|node| node.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Root", "Bend"]);
+        assert_eq!(data.skeleton.nodes[1].parent, Some(0));
+        assert_eq!(data.skeleton.joints.len(), 2);
+
+        // Bound where it rests
+        for matrix in data.skeleton.joint_matrices(&data.skeleton.rest_pose()) {
+            assert!(matrix.is_identity());
+        }
+    }
+
+    #[test]
+    fn loads_skinned_vertices() {
+        let data = column();
+        assert_eq!(data.meshes.len(), 1);
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.indices.len() % 3, 0);
+        for vertex in &mesh.vertices {
+            assert!((vertex.weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+            assert!(vertex.joints.iter().all(|&joint| joint < 2));
+            assert!(vertex.tangent.iter().all(|t| t.is_finite()));
+        }
+    }
+
+    #[test]
+    fn loads_the_clip() {
+        let data = column();
+        assert_eq!(data.clips.len(), 1);
+        let clip = &data.clips[0];
+        assert_eq!(clip.name, "Sway");
+        assert_eq!(clip.duration, 2.0);
+        assert_eq!(clip.channels.len(), 1);
+        assert_eq!(clip.channels[0].node, 1);
+        assert!(matches!(clip.channels[0].track, Track::Rotation(_)));
+    }
+}
@@ -1,4 +1,4 @@
-use image::RgbaImage;
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
 
 #[derive(Debug)]
 pub struct Texture {
@@ -71,11 +71,13 @@ impl Texture {
         })
     }
 
-    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
+    /// With a stencil for the selection outline.
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8; // 1.
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -88,7 +90,7 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
@@ -117,39 +119,413 @@ impl Texture {
             sampler,
         }
     }
+
+    /// Offscreen colour target the size of the surface.
+    ///
+    /// Multisampled targets can't be sampled, they only get resolved.
+    pub(crate) fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: if sample_count > 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            },
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Upload an HDR image as 32-bit floats.
+    ///
+    /// Those aren't filterable on WebGL2, read it with `textureLoad`.
+    pub(crate) fn from_hdr_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: TextureImage,
+        label: &str,
+    ) -> Self {
+        let (width, height) = image.dimensions();
+        let pixels = image.into_rgba32f();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(pixels.as_raw()),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(16 * width),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            size,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// A `size`×`size` HDR cube map that gets drawn into one face and mip
+    /// level at a time, see [`Texture::face_view`].
+    pub(crate) fn create_cube_map(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: super::hdr::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// A 2D view of one face and mip level of a cube map, to render into.
+    pub(crate) fn face_view(&self, face: u32, mip_level: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("cube_face_view"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: mip_level,
+            mip_level_count: std::num::NonZeroU32::new(1),
+            base_array_layer: face,
+            array_layer_count: std::num::NonZeroU32::new(1),
+            ..Default::default()
+        })
+    }
+
+    /// Colour target of a fixed size, for lookup tables baked on the GPU.
+    pub(crate) fn create_baked_target(
+        device: &wgpu::Device,
+        size: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// A `size`³ colour grading LUT that maps every colour to itself.
+    pub(crate) fn create_identity_lut(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: u32,
+    ) -> Self {
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend([r, g, b].map(|c| (c as f32 / max * 255.0).round() as u8));
+                    data.push(255);
+                }
+            }
+        }
+
+        Self::create_lut(device, queue, size, &data)
+    }
+
+    /// Read a LUT laid out as a horizontal strip of `size` slices, each
+    /// `size`×`size`: red grows along x, green along y, blue per slice.
+    pub(crate) fn lut_from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: TextureImage,
+    ) -> Result<(Self, u32), anyhow::Error> {
+        let (width, size) = image.dimensions();
+        if width != size * size {
+            anyhow::bail!("a {}x{} image is not a LUT strip", width, size);
+        }
+
+        let strip = image.into_diffuse_rgba();
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend_from_slice(&strip.get_pixel(b * size + r, g).0);
+                }
+            }
+        }
+
+        Ok((Self::create_lut(device, queue, size, &data), size))
+    }
+
+    fn create_lut(device: &wgpu::Device, queue: &wgpu::Queue, size: u32, data: &[u8]) -> Self {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("lut_texture"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * size),
+                rows_per_image: std::num::NonZeroU32::new(size),
+            },
+            extent,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// The sample count to use for `sample_count`: as is if the adapter can
+    /// multisample both `color_format` and the depth buffer that many times,
+    /// 1 with a warning if it can't.
+    ///
+    /// WebGPU only guarantees 1 and 4, so those are the only counts allowed.
+    pub(crate) fn validate_sample_count(
+        adapter: &wgpu::Adapter,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Result<u32, anyhow::Error> {
+        match sample_count {
+            1 => return Ok(1),
+            4 => {}
+            _ => anyhow::bail!("sample count must be 1 or 4, got {}", sample_count),
+        }
+
+        use wgpu::TextureFormatFeatureFlags as Flags;
+        for (format, flags) in [
+            (
+                color_format,
+                Flags::MULTISAMPLE | Flags::MULTISAMPLE_RESOLVE,
+            ),
+            (Self::DEPTH_FORMAT, Flags::MULTISAMPLE),
+        ] {
+            if !adapter
+                .get_texture_format_features(format)
+                .flags
+                .contains(flags)
+            {
+                gloo::console::warn!(format!(
+                    "Adapter can't multisample {:?}, falling back to no MSAA",
+                    format
+                ));
+                return Ok(1);
+            }
+        }
+
+        Ok(sample_count)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub(crate) struct TextureImage {
-    diffuse_rgba: RgbaImage,
+    image: DynamicImage,
     /// (width, height) of image texture
     dimensions: (u32, u32),
 }
 
 impl TextureImage {
     pub(crate) async fn from_file_name(name: &str) -> Result<Self, anyhow::Error> {
-        Ok(Self::from_bytes(
+        Self::from_bytes(
             &crate::resources::Resources::from_path(&format!("static/{}", name))
                 .request_binary()
                 .await?,
-        ))
+        )
+        .map_err(|err| anyhow::anyhow!("{}: {}", name, err))
     }
 
-    pub(crate) fn from_bytes(diffuse_bytes: &[u8]) -> Self {
-        let diffuse_image = image::load_from_memory(diffuse_bytes).unwrap();
-        let diffuse_rgba = diffuse_image.to_rgba8();
+    pub(crate) fn from_bytes(diffuse_bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        let image = if diffuse_bytes.starts_with(b"#?") {
+            Self::decode_hdr(diffuse_bytes)?
+        } else {
+            image::load_from_memory(diffuse_bytes)?
+        };
 
         use image::GenericImageView;
-        let dimensions = diffuse_image.dimensions();
+        let dimensions = image.dimensions();
+
+        Ok(Self { image, dimensions })
+    }
 
+    /// A single `rgba` pixel, for materials without a texture.
+    pub(crate) fn from_color(rgba: [u8; 4]) -> Self {
         Self {
-            diffuse_rgba,
-            dimensions,
+            image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, image::Rgba(rgba))),
+            dimensions: (1, 1),
         }
     }
 
+    /// Radiance `.hdr` files.
+    ///
+    /// `load_from_memory` decodes these on a thread pool, which doesn't
+    /// exist on wasm, so convert the RGBE pixels here instead.
+    fn decode_hdr(bytes: &[u8]) -> Result<DynamicImage, image::ImageError> {
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::Cursor::new(bytes))?;
+        let meta = decoder.metadata();
+        let pixels = decoder.read_image_native()?;
+
+        let mut image = Rgba32FImage::new(meta.width, meta.height);
+        for (to, from) in image.pixels_mut().zip(pixels) {
+            let [r, g, b] = from.to_hdr().0;
+            *to = image::Rgba([r, g, b, 1.0]);
+        }
+
+        Ok(DynamicImage::ImageRgba32F(image))
+    }
+
+    /// Whether any pixel is less than fully opaque.
+    pub(crate) fn has_alpha(&self) -> bool {
+        self.image.color().has_alpha() && self.image.to_rgba8().pixels().any(|p| p.0[3] < 255)
+    }
+
     pub(crate) fn into_diffuse_rgba(self) -> RgbaImage {
-        self.diffuse_rgba
+        self.image.into_rgba8()
+    }
+
+    pub(crate) fn into_rgba32f(self) -> Rgba32FImage {
+        self.image.into_rgba32f()
     }
 
     pub(crate) fn dimensions(&self) -> (u32, u32) {
@@ -1,4 +1,4 @@
-use gloo::net::http::Request;
+use gloo::net::http::{Request, Response};
 
 pub(crate) struct Resources(String);
 
@@ -12,20 +12,27 @@ impl Resources {
     }
 
     pub(crate) async fn request_string(&self) -> Result<String, anyhow::Error> {
-        Ok(Request::get(&self.0)
-            .header("responseType", "blob")
-            .send()
-            .await?
-            .text()
-            .await?)
+        Ok(self.request().await?.text().await?)
     }
 
     pub(crate) async fn request_binary(&self) -> Result<Vec<u8>, anyhow::Error> {
-        Ok(Request::get(&self.0)
+        Ok(self.request().await?.binary().await?)
+    }
+
+    /// A 404 still sends a page back, so that counts as failing too.
+    async fn request(&self) -> Result<Response, anyhow::Error> {
+        let response = Request::get(&self.0)
             .header("responseType", "blob")
             .send()
-            .await?
-            .binary()
-            .await?)
+            .await?;
+        if !response.ok() {
+            anyhow::bail!(
+                "{}: {} {}",
+                self.0,
+                response.status(),
+                response.status_text()
+            );
+        }
+        Ok(response)
     }
 }
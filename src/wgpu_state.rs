@@ -1,11 +1,14 @@
-use cgmath::prelude::*;
+use std::{collections::HashSet, ops::Range, rc::Rc};
+
 use web_sys::HtmlCanvasElement;
 use wgpu::util::DeviceExt;
 
 use crate::rander::{
-    camera, instance, light,
+    animation, background, camera,
+    command::{Command, Event},
+    culling, environment, gizmo, gpu_culling, hdr, instance, light, lod,
     model::{self, DrawModel},
-    shader, texture,
+    outline, picking, pipeline, post, scene, shader, skinned, texture,
 };
 
 #[derive(Debug)]
@@ -15,31 +18,91 @@ pub(super) struct State {
 
     device: wgpu::Device,
     queue: wgpu::Queue,
-    render_pipeline: wgpu::RenderPipeline,
+    pipeline_cache: pipeline::PipelineCache,
+    pipeline_state: pipeline::PipelineState,
+    render_pipeline: Rc<wgpu::RenderPipeline>,
+    /// Same shader, for materials with `AlphaMode::Blend`.
+    blend_render_pipeline: Rc<wgpu::RenderPipeline>,
+    render_pipeline_layout: Rc<wgpu::PipelineLayout>,
+    shader: shader::Shader,
     height: u32,
     width: u32,
 
     obj_model: model::Model,
+    skinned_models: Vec<skinned::SkinnedModel>,
+    skinned_pass: skinned::SkinnedPass,
+    /// Plays `animation` and the skinned models' clips.
+    clock: animation::Clock,
+    animation: animation::Animation,
 
     light_uniform: light::LightUniform,
     light_buffer: wgpu::Buffer,
     light_bind_group: wgpu::BindGroup,
-    light_render_pipeline: wgpu::RenderPipeline,
+    light_render_pipeline: Rc<wgpu::RenderPipeline>,
+    light_pipeline_layout: Rc<wgpu::PipelineLayout>,
+    light_shader: shader::Shader,
 
     camera: camera::Camera,
+    /// `camera` as of the last `update_camera`, moved by `view`.
+    view_camera: camera::Camera,
+    view: camera::View,
     camera_uniform: camera::CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
 
+    sample_count: u32,
+    /// `None` when `sample_count` is 1 and we draw to the HDR texture directly.
+    msaa_texture: Option<texture::Texture>,
+    /// Skybox and image-based lighting.
+    environment: Option<environment::Environment>,
+    background: background::Background,
+    gradient: background::GradientPass,
+    outline: outline::OutlinePass,
+    gizmo: gizmo::Gizmo,
+    gizmo_pass: gizmo::GizmoPass,
+    post: post::PostStack,
     depth_texture: texture::Texture,
 
     instances: Vec<instance::Instance>,
+    /// Indices into `instances`, the gizmo edits the first one.
+    selection: Vec<usize>,
+    /// Level of detail each instance was drawn at last.
+    instance_lods: Vec<usize>,
+    /// The instances that passed frustum culling, compacted and grouped
+    /// by level of detail.
     instance_buffer: wgpu::Buffer,
+    /// Where each level of detail's instances are in the instance buffer.
+    lod_ranges: Vec<Range<u32>>,
+    /// The instances again, sorted back to front for the blended meshes.
+    blend_instance_buffer: wgpu::Buffer,
+    /// Runs of instances in the blend instance buffer at the same level of
+    /// detail, in the order they're drawn.
+    blend_runs: Vec<(usize, Range<u32>)>,
+    /// Culls the meshes that don't blend on the GPU instead, when the
+    /// adapter supports it.
+    gpu_culling: Option<gpu_culling::GpuCulling>,
+    /// The scene as it was loaded, `scene` fills in what changed since.
+    scene: scene::Scene,
 }
 
 impl State {
-    pub(super) async fn new(canvas: &HtmlCanvasElement) -> Result<Self, anyhow::Error> {
-        let obj_model = "cube.obj";
+    pub(super) async fn new(
+        canvas: &HtmlCanvasElement,
+        sample_count: u32,
+        scene: &scene::Scene,
+        gpu_culling: bool,
+    ) -> Result<Self, anyhow::Error> {
+        let scene_model = match scene.models.as_slice() {
+            [model] => model,
+            models => anyhow::bail!("scenes need exactly one model, not {}", models.len()),
+        };
+        let light = match scene.lights.as_slice() {
+            [] => None,
+            [light] => Some(light),
+            lights => anyhow::bail!("scenes have at most one light, not {}", lights.len()),
+        };
+        let animation =
+            animation::Animation::from_scene(&scene.animations, scene_model.instances.len())?;
 
         let (width, height) = (canvas.width(), canvas.height());
 
@@ -53,12 +116,15 @@ impl State {
             })
             .await
             .unwrap();
+        let gpu_culling = gpu_culling && gpu_culling::GpuCulling::supported(&adapter);
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     features: wgpu::Features::empty(),
-                    limits: if cfg!(target_arch = "wasm32") {
+                    limits: if cfg!(target_arch = "wasm32") && gpu_culling {
+                        wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits())
+                    } else if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
                     } else {
                         wgpu::Limits::default()
@@ -78,6 +144,9 @@ impl State {
         };
         surface.configure(&device, &config);
 
+        let sample_count =
+            texture::Texture::validate_sample_count(&adapter, hdr::FORMAT, sample_count)?;
+
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -115,34 +184,42 @@ impl State {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
 
         //==Model==
-        let obj_model =
-            model::Model::from_file_name(obj_model, &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
+        let model_files = std::iter::once(&scene_model.file)
+            .chain(&scene_model.lods)
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let obj_model = model::Model::from_file_names(
+            &model_files,
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+        )
+        .await?;
 
         //==DeepBuffer==
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+            texture::Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        let msaa_texture = Self::create_msaa_texture(&device, &config, sample_count);
 
         //==Camera==
-        let camera = camera::Camera {
-            // position the camera one unit up and 2 units back
-            // +z is out of the screen
-            eye: (0.0, 0.0, 2.0).into(),
-            // have it look at the origin
-            target: (0.0, 0.0, 0.0).into(),
-            // which way is "up"
-            up: cgmath::Vector3::unit_y(),
-            aspect: config.width as f32 / config.height as f32,
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 100.0,
-        };
+        let camera = scene
+            .camera
+            .to_camera(config.width as f32 / config.height as f32);
 
         let mut camera_uniform = camera::CameraUniform::new();
         camera_uniform.update_view_proj(&camera);
@@ -178,12 +255,16 @@ impl State {
         });
 
         //==Light==
-        let light_uniform = light::LightUniform {
-            position: [2.0, 2.0, 2.0],
-            _padding: 0,
-            color: [1.0, 1.0, 1.0],
-            _padding2: 0,
-        };
+        // No light is a black one
+        let light_uniform = light.map_or(
+            light::LightUniform {
+                position: [0.0, 0.0, 0.0],
+                _padding: 0,
+                color: [0.0, 0.0, 0.0],
+                _padding2: 0,
+            },
+            light::LightUniform::from,
+        );
 
         let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Light VB"),
@@ -215,46 +296,54 @@ impl State {
             label: None,
         });
 
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        let mut pipeline_cache = pipeline::PipelineCache::default();
+        let pipeline_state = pipeline::PipelineState::default().sample_count(sample_count);
+
+        //==HDR==
+        let post = post::PostStack::new(&device, &queue, &config, &mut pipeline_cache).await?;
+
+        let light_pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
                 label: Some("Light Pipeline Layout"),
                 bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
                 push_constant_ranges: &[],
-            });
-            shader::Shader::from_file_name("Light Shader", "light.wgsl")
-                .await?
-                .create_render_pipeline(
+            },
+        ));
+        let light_shader = shader::Shader::from_file_name("Light Shader", "light.wgsl").await?;
+        let light_render_pipeline = pipeline_cache.get_or_create(
+            &device,
+            &light_shader,
+            &light_pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &Self::light_vertex_layouts(),
+            &pipeline_state,
+        )?;
+
+        //==Environment==
+        let environment = match &scene.environment {
+            Some(file_name) => Some(
+                environment::Environment::from_file_name(
                     &device,
-                    &layout,
-                    config.format,
-                    Some(texture::Texture::DEPTH_FORMAT),
-                    &[<model::ModelVertex as model::Vertex>::desc()],
+                    &queue,
+                    &mut pipeline_cache,
+                    &camera_bind_group_layout,
+                    &pipeline_state,
+                    file_name,
                 )
+                .await?,
+            ),
+            None => None,
         };
 
-        //==Instances==
-        let instances = (0..instance::NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..instance::NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = instance::SPACE_BETWEEN
-                        * (x as f32 - instance::NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = instance::SPACE_BETWEEN
-                        * (z as f32 - instance::NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
-
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
+        let gradient =
+            background::GradientPass::new(&device, &mut pipeline_cache, &pipeline_state).await?;
 
-                    instance::Instance { position, rotation }
-                })
-            })
+        //==Instances==
+        let instances = scene_model
+            .instances
+            .iter()
+            .map(instance::Instance::from)
             .collect::<Vec<_>>();
 
         let instance_data = instances
@@ -264,33 +353,117 @@ impl State {
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
             contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let mut blend_order = (0..instances.len()).collect::<Vec<_>>();
+        instance::back_to_front(&mut blend_order, &instances, camera.eye);
+        let blend_instance_data = blend_order
+            .iter()
+            .map(|&i| instances[i].to_raw())
+            .collect::<Vec<_>>();
+        let blend_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Blend Instance Buffer"),
+            contents: bytemuck::cast_slice(&blend_instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
         //==Shader==
-        let shader = shader::Shader::from_file_name("Normal Shader", "shader.wgsl");
+        let mut defines = vec!["NORMAL_MAP"];
+        let mut bind_group_layouts = vec![
+            &texture_bind_group_layout,
+            &camera_bind_group_layout,
+            &light_bind_group_layout,
+        ];
+        if let Some(environment) = &environment {
+            defines.push("IBL");
+            bind_group_layouts.push(&environment.ibl_layout);
+        }
+
+        let shader =
+            shader::Shader::from_file_name_with_defines("Normal Shader", "shader.wgsl", &defines)
+                .await?;
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        let render_pipeline_layout = Rc::new(device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    &camera_bind_group_layout,
-                    &light_bind_group_layout,
-                ],
+                bind_group_layouts: &bind_group_layouts,
                 push_constant_ranges: &[],
-            });
+            },
+        ));
 
-        let render_pipeline = shader.await?.create_render_pipeline(
+        let render_pipeline = pipeline_cache.get_or_create(
             &device,
+            &shader,
             &render_pipeline_layout,
-            config.format,
+            hdr::FORMAT,
             Some(texture::Texture::DEPTH_FORMAT),
-            &[
-                <model::ModelVertex as model::Vertex>::desc(),
-                instance::InstanceRaw::desc(),
-            ],
-        );
+            &Self::vertex_layouts(),
+            &pipeline_state,
+        )?;
+        let blend_render_pipeline = pipeline_cache.get_or_create(
+            &device,
+            &shader,
+            &render_pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &Self::vertex_layouts(),
+            &Self::blend_pipeline_state(&pipeline_state),
+        )?;
+
+        let skinned_pass = skinned::SkinnedPass::new(
+            &device,
+            &mut pipeline_cache,
+            &texture_bind_group_layout,
+            &camera_bind_group_layout,
+            environment
+                .as_ref()
+                .map(|environment| &environment.ibl_layout),
+            &defines,
+            &pipeline_state,
+        )
+        .await?;
+        let mut skinned_models = Vec::new();
+        for desc in &scene.skinned_models {
+            skinned_models.push(
+                skinned::SkinnedModel::new(
+                    desc,
+                    &device,
+                    &queue,
+                    &texture_bind_group_layout,
+                    &skinned_pass.layout,
+                    &light_buffer,
+                )
+                .await?,
+            );
+        }
+
+        let outline = outline::OutlinePass::new(
+            &device,
+            &mut pipeline_cache,
+            &camera_bind_group_layout,
+            &pipeline_state,
+            instances.len(),
+        )
+        .await?;
+
+        let gizmo_pass = gizmo::GizmoPass::new(
+            &device,
+            &mut pipeline_cache,
+            &camera_bind_group_layout,
+            &pipeline_state,
+        )
+        .await?;
+
+        let gpu_culling = if gpu_culling {
+            Some(gpu_culling::GpuCulling::new(&device, &instance_data, &obj_model).await?)
+        } else {
+            None
+        };
+
+        // Everything at full detail until the first `update`
+        let mut lod_ranges = vec![0..0; obj_model.lod_count()];
+        lod_ranges[0] = 0..instances.len() as u32;
+        let blend_runs = vec![(0, 0..instances.len() as u32)];
 
         Ok(Self {
             surface,
@@ -298,67 +471,718 @@ impl State {
             device,
             queue,
 
+            pipeline_cache,
+            pipeline_state,
             render_pipeline,
+            blend_render_pipeline,
+            render_pipeline_layout,
+            shader,
 
             height,
             width,
 
             obj_model,
+            skinned_models,
+            skinned_pass,
+            clock: animation::Clock::new(),
+            animation,
 
             light_uniform,
             light_buffer,
             light_bind_group,
             light_render_pipeline,
+            light_pipeline_layout,
+            light_shader,
 
+            sample_count,
+            msaa_texture,
+            environment,
+            background: background::Background::default(),
+            gradient,
+            outline,
+            gizmo: gizmo::Gizmo::default(),
+            gizmo_pass,
+            post,
             depth_texture,
 
+            view_camera: camera.clone(),
             camera,
+            view: camera::View::default(),
             camera_uniform,
             camera_buffer,
             camera_bind_group,
 
+            instance_lods: vec![0; instances.len()],
+            lod_ranges,
             instances,
+            selection: Vec::new(),
             instance_buffer,
+            blend_instance_buffer,
+            blend_runs,
+            gpu_culling,
+            scene: scene.clone(),
         })
     }
 
-    pub(super) fn update(&mut self, width: u32, height: u32, cursor_to: (i32, i32)) {
+    fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<texture::Texture> {
+        (sample_count > 1).then(|| {
+            texture::Texture::create_render_target(
+                device,
+                config,
+                hdr::FORMAT,
+                sample_count,
+                "msaa_texture",
+            )
+        })
+    }
+
+    /// Blended meshes are tested against the opaque ones but don't hide
+    /// each other.
+    fn blend_pipeline_state(state: &pipeline::PipelineState) -> pipeline::PipelineState {
+        state
+            .clone()
+            .blend(Some(wgpu::BlendState::ALPHA_BLENDING))
+            .depth(wgpu::CompareFunction::Less, false)
+    }
+
+    fn vertex_layouts<'a>() -> [wgpu::VertexBufferLayout<'a>; 2] {
+        [
+            <model::ModelVertex as model::Vertex>::desc(),
+            instance::InstanceRaw::desc(),
+        ]
+    }
+
+    fn light_vertex_layouts<'a>() -> [wgpu::VertexBufferLayout<'a>; 1] {
+        [<model::ModelVertex as model::Vertex>::desc()]
+    }
+
+    /// Refetch every shader and rebuild the pipelines whose source changed.
+    ///
+    /// A shader that fails to compile keeps its old pipeline, the error
+    /// is returned so the caller can show it.
+    pub(super) async fn reload_shaders(&mut self) -> Result<(), anyhow::Error> {
+        let mut errors = Vec::new();
+
+        match Self::reload_shader(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.shader,
+            &self.render_pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &Self::vertex_layouts(),
+            &self.pipeline_state,
+        )
+        .await
+        {
+            Ok(Some((shader, pipeline))) => {
+                match self.pipeline_cache.get_or_create(
+                    &self.device,
+                    &shader,
+                    &self.render_pipeline_layout,
+                    hdr::FORMAT,
+                    Some(texture::Texture::DEPTH_FORMAT),
+                    &Self::vertex_layouts(),
+                    &Self::blend_pipeline_state(&self.pipeline_state),
+                ) {
+                    Ok(blend_pipeline) => self.blend_render_pipeline = blend_pipeline,
+                    Err(err) => errors.push(err.to_string()),
+                }
+                self.shader = shader;
+                self.render_pipeline = pipeline;
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        match Self::reload_shader(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.light_shader,
+            &self.light_pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &Self::light_vertex_layouts(),
+            &self.pipeline_state,
+        )
+        .await
+        {
+            Ok(Some((shader, pipeline))) => {
+                self.light_shader = shader;
+                self.light_render_pipeline = pipeline;
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        if let Some(environment) = &mut self.environment {
+            match Self::reload_shader(
+                &self.device,
+                &mut self.pipeline_cache,
+                &environment.skybox_shader,
+                &environment.skybox_pipeline_layout,
+                hdr::FORMAT,
+                Some(texture::Texture::DEPTH_FORMAT),
+                &[],
+                &background::pipeline_state(&self.pipeline_state),
+            )
+            .await
+            {
+                Ok(Some((shader, pipeline))) => {
+                    environment.skybox_shader = shader;
+                    environment.skybox_pipeline = pipeline;
+                }
+                Ok(None) => {}
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+
+        match Self::reload_shader(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.gradient.shader,
+            &self.gradient.pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[],
+            &background::pipeline_state(&self.pipeline_state),
+        )
+        .await
+        {
+            Ok(Some((shader, pipeline))) => {
+                self.gradient.shader = shader;
+                self.gradient.pipeline = pipeline;
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        match Self::reload_shader(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.outline.shader,
+            &self.outline.pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &outline::vertex_layouts(),
+            &outline::mark_state(&self.pipeline_state),
+        )
+        .await
+        {
+            Ok(Some((shader, mark_pipeline))) => {
+                match self.pipeline_cache.get_or_create(
+                    &self.device,
+                    &shader,
+                    &self.outline.pipeline_layout,
+                    hdr::FORMAT,
+                    Some(texture::Texture::DEPTH_FORMAT),
+                    &outline::vertex_layouts(),
+                    &outline::outline_state(&self.pipeline_state),
+                ) {
+                    Ok(pipeline) => self.outline.pipeline = pipeline,
+                    Err(err) => errors.push(err.to_string()),
+                }
+                self.outline.shader = shader;
+                self.outline.mark_pipeline = mark_pipeline;
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        match Self::reload_shader(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.skinned_pass.shader,
+            &self.skinned_pass.pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &skinned::vertex_layouts(),
+            &self.pipeline_state,
+        )
+        .await
+        {
+            Ok(Some((shader, pipeline))) => {
+                self.skinned_pass.shader = shader;
+                self.skinned_pass.pipeline = pipeline;
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        match Self::reload_shader(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.gizmo_pass.shader,
+            &self.gizmo_pass.pipeline_layout,
+            hdr::FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            &[gizmo::GizmoVertex::desc()],
+            &gizmo::pipeline_state(&self.pipeline_state),
+        )
+        .await
+        {
+            Ok(Some((shader, pipeline))) => {
+                self.gizmo_pass.shader = shader;
+                self.gizmo_pass.pipeline = pipeline;
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err.to_string()),
+        }
+
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            if let Err(err) = gpu_culling.reload_shader(&self.device).await {
+                errors.push(err.to_string());
+            }
+        }
+
+        for pass in self.post.passes_mut() {
+            match Self::reload_shader(
+                &self.device,
+                &mut self.pipeline_cache,
+                &pass.shader,
+                &pass.pipeline_layout,
+                pass.format,
+                None,
+                &[],
+                &post::PostPass::pipeline_state(),
+            )
+            .await
+            {
+                Ok(Some((shader, pipeline))) => {
+                    pass.shader = shader;
+                    pass.pipeline = pipeline;
+                }
+                Ok(None) => {}
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(errors.join("\n")))
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn reload_shader(
+        device: &wgpu::Device,
+        pipeline_cache: &mut pipeline::PipelineCache,
+        old_shader: &shader::Shader,
+        layout: &Rc<wgpu::PipelineLayout>,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        vertex_layouts: &[wgpu::VertexBufferLayout<'_>],
+        state: &pipeline::PipelineState,
+    ) -> Result<Option<(shader::Shader, Rc<wgpu::RenderPipeline>)>, anyhow::Error> {
+        let shader = match old_shader.refetch().await? {
+            Some(shader) => shader,
+            None => return Ok(None),
+        };
+
+        let key = pipeline::PipelineKey::new(
+            &shader,
+            layout,
+            color_format,
+            depth_format,
+            vertex_layouts,
+            state,
+        );
+        let pipeline = match pipeline_cache.get(&key) {
+            Some(pipeline) => pipeline,
+            None => {
+                let pipeline = shader
+                    .try_create_render_pipeline(
+                        device,
+                        layout,
+                        color_format,
+                        depth_format,
+                        vertex_layouts,
+                        state,
+                    )
+                    .await?;
+                pipeline_cache.insert(key, layout, pipeline)
+            }
+        };
+        pipeline_cache.invalidate_shader(old_shader);
+
+        Ok(Some((shader, pipeline)))
+    }
+
+    /// Do what `command` says, answering with an `Event` for the commands
+    /// that have one.
+    pub(super) fn handle(&mut self, command: Command) -> Result<Option<Event>, anyhow::Error> {
+        match command {
+            Command::Resize { width, height } => self.resize(width, height),
+            Command::CameraInput { view } => self.set_view(view),
+            Command::SetLight(light) => self.set_light(&light),
+            Command::SpawnInstance(instance) => {
+                self.spawn_instance(instance::Instance::from(&instance))
+            }
+            Command::SetTonemapping {
+                tonemapping,
+                exposure,
+            } => self.set_tonemapping(tonemapping, exposure),
+            Command::SetBackground(background) => self.set_background(background),
+            Command::SetPostEffect { effect, enabled } => self.set_post_effect(effect, enabled),
+            Command::SetLut(image) => self.post.set_lut(&self.device, &self.queue, image)?,
+            Command::SetOutline(outline) => self.set_outline(outline),
+            Command::SetSelection(selected) => self.set_selection(&selected),
+            Command::SetGizmo(mode) => self.set_gizmo(mode),
+            Command::SetPlaying(playing) => self.set_playing(playing),
+            Command::Seek(time) => self.seek(time),
+            Command::Pick { pixel } => return Ok(Some(Event::Picked(self.pick(pixel)))),
+            Command::Pointer { pixel, pressed } => {
+                return Ok(self.pointer(pixel, pressed).map(Event::GizmoDrag))
+            }
+            Command::Save => return Ok(Some(Event::Saved(self.scene().to_json()?))),
+        }
+        Ok(None)
+    }
+
+    fn set_tonemapping(&mut self, tonemapping: hdr::Tonemapping, exposure: f32) {
+        self.post
+            .tonemap
+            .set_params(&self.queue, tonemapping.params(exposure));
+    }
+
+    fn set_background(&mut self, background: background::Background) {
+        if let background::Background::Gradient(top, bottom) = background {
+            self.gradient.set_colors(&self.queue, top, bottom);
+        }
+        self.background = background;
+    }
+
+    fn set_outline(&mut self, outline: outline::Outline) {
+        self.outline.set_outline(&self.queue, outline);
+    }
+
+    /// Outline the instances at these indices, out of range and repeated
+    /// ones are ignored. The outline's buffer only fits each instance once.
+    fn set_selection(&mut self, selected: &[usize]) {
+        let mut seen = HashSet::new();
+        self.selection = selected
+            .iter()
+            .copied()
+            .filter(|&i| i < self.instances.len() && seen.insert(i))
+            .collect();
+        self.outline.set_selection(
+            &self.queue,
+            self.selection.iter().map(|&i| &self.instances[i]),
+        );
+    }
+
+    fn set_gizmo(&mut self, mode: Option<gizmo::GizmoMode>) {
+        self.gizmo.set_mode(mode);
+    }
+
+    /// Move the gizmo's handles with the pointer at `pixel`, in canvas
+    /// pixels. Returns whether a handle is being dragged when that changes.
+    fn pointer(&mut self, pixel: (f32, f32), pressed: bool) -> Option<bool> {
+        let was_dragging = self.gizmo.dragging();
+        let ray = picking::Ray::from_pixel(&self.view_camera, pixel, (self.width, self.height))?;
+
+        let selected = self.selection.first().copied();
+        let moved = self.gizmo.pointer(
+            &ray,
+            pressed,
+            selected.map(|i| &self.instances[i]),
+            &self.view_camera,
+        );
+        if let (Some(i), Some(instance)) = (selected, moved) {
+            self.set_instance(i, instance);
+        }
+
+        (self.gizmo.dragging() != was_dragging).then(|| self.gizmo.dragging())
+    }
+
+    /// Replace an instance and everything drawn from it.
+    fn set_instance(&mut self, index: usize, instance: instance::Instance) {
+        self.set_instances([(index, instance)]);
+    }
+
+    /// Replace instances by index and everything drawn from them.
+    fn set_instances(&mut self, instances: impl IntoIterator<Item = (usize, instance::Instance)>) {
+        let mut changed = false;
+        for (index, instance) in instances {
+            if self.instances[index] == instance {
+                continue;
+            }
+            if let Some(gpu_culling) = &self.gpu_culling {
+                gpu_culling.set_instance(&self.queue, index, instance.to_raw());
+            }
+            self.instances[index] = instance;
+            changed = true;
+        }
+        if !changed {
+            return;
+        }
+
+        self.cull_instances(&self.view_camera.clone());
+        self.outline.set_selection(
+            &self.queue,
+            self.selection.iter().map(|&i| &self.instances[i]),
+        );
+    }
+
+    /// Add an instance after the others, growing everything sized by the
+    /// instance count.
+    fn spawn_instance(&mut self, instance: instance::Instance) {
+        self.instances.push(instance);
+        self.instance_lods.push(0);
+
+        let size = (std::mem::size_of::<instance::InstanceRaw>() * self.instances.len())
+            as wgpu::BufferAddress;
+        self.instance_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        self.blend_instance_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Blend Instance Buffer"),
+            size,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            let instance_data = self
+                .instances
+                .iter()
+                .map(instance::Instance::to_raw)
+                .collect::<Vec<_>>();
+            gpu_culling.set_instances(&self.device, &self.queue, &instance_data);
+        }
+        self.outline.reserve(&self.device, self.instances.len());
+
+        self.cull_instances(&self.view_camera.clone());
+        self.outline.set_selection(
+            &self.queue,
+            self.selection.iter().map(|&i| &self.instances[i]),
+        );
+    }
+
+    /// Move and recolour the light, until the scene's animations do.
+    fn set_light(&mut self, light: &scene::SceneLight) {
+        self.light_uniform = light::LightUniform::from(light);
+        self.queue.write_buffer(
+            &self.light_buffer,
+            0,
+            bytemuck::cast_slice(&[self.light_uniform]),
+        );
+    }
+
+    fn set_post_effect(&mut self, effect: post::Effect, enabled: bool) {
+        self.post.set_enabled(effect, enabled);
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        self.clock.set_playing(playing);
+    }
+
+    /// Jump the animations and clips to `time` seconds in.
+    fn seek(&mut self, time: f32) {
+        self.clock.seek(time);
+    }
+
+    fn set_view(&mut self, view: camera::View) {
+        self.view = view;
+        self.update_camera();
+    }
+
+    /// Largest width or height `resize` takes.
+    pub(super) fn max_size(&self) -> u32 {
+        self.device.limits().max_texture_dimension_2d
+    }
+
+    /// Reconfigure the surface and everything sized like it, if the size
+    /// changed.
+    fn resize(&mut self, width: u32, height: u32) {
+        if (width, height) == (self.width, self.height) || width == 0 || height == 0 {
+            return;
+        }
         (self.width, self.height) = (width, height);
 
         (self.config.width, self.config.height) = (width, height);
 
         self.surface.configure(&self.device, &self.config);
-        self.depth_texture =
-            texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.sample_count,
+            "depth_texture",
+        );
+        self.msaa_texture =
+            Self::create_msaa_texture(&self.device, &self.config, self.sample_count);
+        self.post.resize(&self.device, &self.config);
+        self.outline.resize(&self.queue, width, height);
 
+        self.update_camera();
+    }
+
+    /// Move the camera we draw with to `camera` moved by the view.
+    fn update_camera(&mut self) {
         let camera = camera::Camera {
-            aspect: width as f32 / height as f32,
-            eye: self.camera.eye
-                + cgmath::Vector3::new(cursor_to.0 as f32 / 10.0, -cursor_to.1 as f32 / 10.0, 0.0),
-            ..self.camera
+            aspect: self.width as f32 / self.height as f32,
+            ..self.view.apply(&self.camera)
         };
 
         self.camera_uniform.update_view_proj(&camera);
+        self.cull_instances(&camera);
+        if let Some(gpu_culling) = &mut self.gpu_culling {
+            gpu_culling.update(&self.queue, &camera);
+        }
 
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+        self.view_camera = camera;
     }
 
-    pub(super) fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
+    /// What is drawn at `pixel`, in canvas pixels from the top left.
+    fn pick(&self, pixel: (f32, f32)) -> Option<picking::Pick> {
+        let ray = picking::Ray::from_pixel(&self.view_camera, pixel, (self.width, self.height))?;
+        picking::pick(&ray, &self.obj_model, &self.instances)
+    }
+
+    /// The scene as it is now, with the instances wherever the gizmo left
+    /// them.
+    fn scene(&self) -> scene::Scene {
+        let mut scene = self.scene.clone();
+        scene.models[0].instances = self
+            .instances
+            .iter()
+            .map(scene::SceneInstance::from)
+            .collect();
+        scene.camera = scene::SceneCamera::from(&self.camera);
+        if let Some(light) = scene.lights.first_mut() {
+            *light = scene::SceneLight::from(&self.light_uniform);
+        }
+        scene
+    }
+
+    /// Upload only the instances `camera` can see, one bucket per level of
+    /// detail, and those again back to front for the blended pass.
+    ///
+    /// With GPU culling the compute shader does this for the opaque pass,
+    /// so only the blended pass is left, if the model has one.
+    fn cull_instances(&mut self, camera: &camera::Camera) {
+        let levels = self.obj_model.lod_count();
+        if self.gpu_culling.is_some() && !self.obj_model.blends() {
+            self.lod_ranges = vec![0..0; levels];
+            self.blend_runs = Vec::new();
+            return;
+        }
+
+        let frustum = culling::Frustum::from_view_projection(camera.build_view_projection_matrix());
+        let bounds = self.obj_model.bounding_sphere();
+
+        let mut visible = Vec::new();
+        for i in culling::visible_instances(&self.instances, &bounds, &frustum) {
+            let size = lod::screen_size(
+                &bounds.transformed(&self.instances[i]),
+                camera.eye,
+                cgmath::Deg(camera.fovy).into(),
+            );
+            self.instance_lods[i] = lod::select(self.instance_lods[i], size, levels);
+            visible.push(i);
+        }
+
+        if self.gpu_culling.is_none() {
+            let mut buckets = vec![Vec::new(); levels];
+            for &i in &visible {
+                buckets[self.instance_lods[i]].push(self.instances[i].to_raw());
+            }
+            let mut start = 0;
+            self.lod_ranges = buckets
+                .iter()
+                .map(|bucket| {
+                    let range = start..start + bucket.len() as u32;
+                    start = range.end;
+                    range
+                })
+                .collect();
+            if !visible.is_empty() {
+                self.queue.write_buffer(
+                    &self.instance_buffer,
+                    0,
+                    bytemuck::cast_slice(&buckets.concat()),
+                );
+            }
+        }
+
+        // Levels go by size on screen, not distance, so a finer level can
+        // be behind a coarser one. Sort them all together instead.
+        instance::back_to_front(&mut visible, &self.instances, camera.eye);
+        self.blend_runs = lod::runs(visible.iter().map(|&i| self.instance_lods[i]));
+        if visible.is_empty() {
+            return;
+        }
+        let blend_instance_data = visible
+            .iter()
+            .map(|&i| self.instances[i].to_raw())
+            .collect::<Vec<_>>();
+        self.queue.write_buffer(
+            &self.blend_instance_buffer,
+            0,
+            bytemuck::cast_slice(&blend_instance_data),
+        );
+    }
+
+    /// Move whatever the scene's animations move to where it is `time`
+    /// seconds in.
+    fn animate(&mut self, time: f32) {
+        if self.animation.is_empty() {
+            return;
+        }
+
+        let mut instances = self.instances.clone();
+        self.animation.apply(
+            time,
+            &mut instances,
+            &mut self.light_uniform,
+            &mut self.camera,
+        );
+        self.set_instances(instances.into_iter().enumerate());
+        if self.animation.animates_camera() {
+            self.update_camera();
+        }
+    }
+
+    /// Step the animations and clips `delta` seconds forward, when playing.
+    pub(super) fn advance(&mut self, delta: f32) {
+        let time = self.clock.advance(delta);
+        self.animate(time);
         self.queue.write_buffer(
             &self.light_buffer,
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
 
+        for model in &self.skinned_models {
+            model.update(&self.queue, time);
+        }
+    }
+
+    /// Configure the surface again after it was lost or went out of date.
+    pub(super) fn reconfigure(&mut self) {
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    pub(super) fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let gizmo_vertices = match self.selection.first() {
+            Some(&i) => self.gizmo.vertices(&self.instances[i], &self.view_camera),
+            None => Vec::new(),
+        };
+        self.gizmo_pass
+            .set_vertices(&self.device, &self.queue, &gizmo_vertices);
+
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -370,19 +1194,21 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        if let Some(gpu_culling) = &self.gpu_culling {
+            gpu_culling.dispatch(&self.queue, &mut encoder);
+        }
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: self
+                        .msaa_texture
+                        .as_ref()
+                        .map_or(self.post.scene_view(), |t| &t.view),
+                    resolve_target: self.msaa_texture.as_ref().map(|_| self.post.scene_view()),
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(self.background.clear_color()),
                         store: true,
                     },
                 })],
@@ -392,7 +1218,10 @@ impl State {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: true,
                     }),
-                    stencil_ops: None,
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: true,
+                    }),
                 }),
             });
 
@@ -409,14 +1238,70 @@ impl State {
             );
 
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
+            if let Some(environment) = &self.environment {
+                render_pass.set_bind_group(3, &environment.ibl_bind_group, &[]);
+            }
+            match &self.gpu_culling {
+                Some(gpu_culling) => gpu_culling.draw(
+                    &mut render_pass,
+                    &self.obj_model,
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                ),
+                None => {
+                    for (lod, instances) in self.lod_ranges.iter().enumerate() {
+                        render_pass.draw_model_instanced_blended(
+                            &self.obj_model,
+                            lod,
+                            false,
+                            instances.clone(),
+                            &self.camera_bind_group,
+                            &self.light_bind_group,
+                        );
+                    }
+                }
+            }
+            self.skinned_pass.draw(
+                &mut render_pass,
+                &self.skinned_models,
                 &self.camera_bind_group,
-                &self.light_bind_group,
+                self.environment
+                    .as_ref()
+                    .map(|environment| &environment.ibl_bind_group),
             );
+
+            match (self.background, &self.environment) {
+                (background::Background::Gradient(..), _) => self.gradient.draw(&mut render_pass),
+                (background::Background::Skybox, Some(environment)) => {
+                    environment.draw_skybox(&mut render_pass, &self.camera_bind_group)
+                }
+                _ => {}
+            }
+
+            render_pass.set_pipeline(&self.blend_render_pipeline);
+            if let Some(environment) = &self.environment {
+                render_pass.set_bind_group(3, &environment.ibl_bind_group, &[]);
+            }
+            render_pass.set_vertex_buffer(1, self.blend_instance_buffer.slice(..));
+            for (lod, instances) in &self.blend_runs {
+                render_pass.draw_model_instanced_blended(
+                    &self.obj_model,
+                    *lod,
+                    true,
+                    instances.clone(),
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                );
+            }
+
+            self.outline
+                .draw(&mut render_pass, &self.obj_model, &self.camera_bind_group);
+            self.gizmo_pass
+                .draw(&mut render_pass, &self.camera_bind_group);
         }
 
+        self.post.run(&mut encoder, &view);
+
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
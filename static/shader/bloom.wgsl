@@ -0,0 +1,31 @@
+#include "common/fullscreen.wgsl"
+
+// x: threshold, y: intensity, z: radius in texels
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+fn bright(color: vec3<f32>) -> vec3<f32> {
+    let luma = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return color * max(luma - params.x, 0.0) / max(luma, 0.0001);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+
+    // Gaussian weighted 5x5 taps spread `radius` texels apart
+    var bloom = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var y = -2; y <= 2; y = y + 1) {
+        for (var x = -2; x <= 2; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y));
+            let weight = exp(-dot(offset, offset) / 4.0);
+            let tap = textureSample(t_input, s_input, in.tex_coords + offset * texel * params.z);
+            bloom = bloom + bright(tap.rgb) * weight;
+            total = total + weight;
+        }
+    }
+
+    return vec4<f32>(color.rgb + bloom / total * params.y, color.a);
+}
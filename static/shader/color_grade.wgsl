@@ -0,0 +1,23 @@
+#include "common/fullscreen.wgsl"
+
+// x: strength, y: LUT size
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+@group(1) @binding(1)
+var t_lut: texture_3d<f32>;
+@group(1) @binding(2)
+var s_lut: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+
+    // LUTs are authored in sRGB, the input is linear
+    let encoded = pow(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(1.0 / 2.2));
+    // Sample texel centres so the ends of the range aren't blended with the border
+    let size = params.y;
+    let coords = encoded * (size - 1.0) / size + 0.5 / size;
+    let graded = pow(textureSample(t_lut, s_lut, coords).rgb, vec3<f32>(2.2));
+
+    return vec4<f32>(mix(color.rgb, graded, params.x), color.a);
+}
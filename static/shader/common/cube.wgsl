@@ -0,0 +1,49 @@
+// Direction through `uv` on a cube map face. Faces are in layer order:
+// +X, -X, +Y, -Y, +Z, -Z.
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let p = uv * 2.0 - 1.0;
+    if (face == 0u) {
+        return normalize(vec3<f32>(1.0, -p.y, -p.x));
+    }
+    if (face == 1u) {
+        return normalize(vec3<f32>(-1.0, -p.y, p.x));
+    }
+    if (face == 2u) {
+        return normalize(vec3<f32>(p.x, 1.0, p.y));
+    }
+    if (face == 3u) {
+        return normalize(vec3<f32>(p.x, -1.0, -p.y));
+    }
+    if (face == 4u) {
+        return normalize(vec3<f32>(p.x, -p.y, 1.0));
+    }
+    return normalize(vec3<f32>(-p.x, -p.y, -1.0));
+}
+
+// Low discrepancy point `i` of `count`, for importance sampling.
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    var bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2<f32>(f32(i) / f32(count), f32(bits) * 2.3283064365386963e-10);
+}
+
+// GGX distributed half vector around `n`.
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * 3.14159265 * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let h = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if (abs(n.z) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return normalize(tangent * h.x + bitangent * h.y + n * h.z);
+}
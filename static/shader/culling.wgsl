@@ -0,0 +1,90 @@
+// Frustum culling and level of detail selection for every instance, the
+// same as `State::cull_instances` but without the round trip to the CPU.
+
+struct Culling {
+    // Frustum planes, normals pointing inwards.
+    planes: array<vec4<f32>, 6>,
+    // Model space bounding sphere, xyz center, w radius.
+    bounds: vec4<f32>,
+    // xyz camera eye, w tan(fovy / 2).
+    eye: vec4<f32>,
+    // Screen sizes under which each level after the first takes over,
+    // w the hysteresis.
+    thresholds: vec4<f32>,
+    // x number of instances, y number of thresholds in use.
+    counts: vec4<u32>,
+}
+
+@group(0) @binding(0)
+var<uniform> culling: Culling;
+// `InstanceRaw`s, 25 floats each.
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+// One region of `counts.x` instances per level of detail.
+@group(0) @binding(2)
+var<storage, read_write> visible: array<f32>;
+// Visible instances in each level's region.
+@group(0) @binding(3)
+var<storage, read_write> visible_counts: array<atomic<u32>, 4>;
+// Level each instance was drawn at last.
+@group(0) @binding(4)
+var<storage, read_write> lods: array<u32>;
+
+fn select_level(previous: u32, size: f32) -> u32 {
+    let thresholds = culling.counts.y;
+    let hysteresis = culling.thresholds.w;
+
+    // Within the band of the previous level, widened by the hysteresis
+    let previous = min(previous, thresholds);
+    let above_lower = previous >= thresholds || size >= culling.thresholds[previous] * (1.0 - hysteresis);
+    let below_upper = previous == 0u || size <= culling.thresholds[previous - 1u] * (1.0 + hysteresis);
+    if (above_lower && below_upper) {
+        return previous;
+    }
+
+    var level = 0u;
+    for (var i = 0u; i < thresholds; i = i + 1u) {
+        if (size < culling.thresholds[i]) {
+            level = level + 1u;
+        }
+    }
+    return level;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance = id.x;
+    if (instance >= culling.counts.x) {
+        return;
+    }
+
+    let base = instance * 25u;
+    let model = mat4x4<f32>(
+        vec4<f32>(instances[base], instances[base + 1u], instances[base + 2u], instances[base + 3u]),
+        vec4<f32>(instances[base + 4u], instances[base + 5u], instances[base + 6u], instances[base + 7u]),
+        vec4<f32>(instances[base + 8u], instances[base + 9u], instances[base + 10u], instances[base + 11u]),
+        vec4<f32>(instances[base + 12u], instances[base + 13u], instances[base + 14u], instances[base + 15u])
+    );
+    let center = (model * vec4<f32>(culling.bounds.xyz, 1.0)).xyz;
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = culling.bounds.w * scale;
+
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = culling.planes[i];
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return;
+        }
+    }
+
+    let distance = length(culling.eye.xyz - center);
+    var level = 0u;
+    if (distance > radius) {
+        level = select_level(lods[instance], radius / (distance * culling.eye.w));
+    }
+    lods[instance] = level;
+
+    let slot = level * culling.counts.x + atomicAdd(&visible_counts[level], 1u);
+    for (var i = 0u; i < 25u; i = i + 1u) {
+        visible[slot * 25u + i] = instances[base + i];
+    }
+}
@@ -0,0 +1,37 @@
+#define CUSTOM_INPUT
+#include "common/fullscreen.wgsl"
+#include "common/cube.wgsl"
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+// Scale and bias to F0 of the specular BRDF, x along n·v, y along roughness.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.tex_coords.x, 0.001);
+    let roughness = in.tex_coords.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+
+    let sample_count = 256u;
+    var a = 0.0;
+    var b = 0.0;
+    for (var i = 0u; i < sample_count; i = i + 1u) {
+        let h = importance_sample_ggx(hammersley(i, sample_count), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if (n_dot_l > 0.0) {
+            let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            a = a + (1.0 - fc) * g_vis;
+            b = b + fc * g_vis;
+        }
+    }
+
+    return vec4<f32>(a / f32(sample_count), b / f32(sample_count), 0.0, 1.0);
+}
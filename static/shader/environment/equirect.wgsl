@@ -0,0 +1,24 @@
+#define CUSTOM_INPUT
+#include "common/fullscreen.wgsl"
+#include "common/cube.wgsl"
+
+@group(0) @binding(0)
+var t_equirect: texture_2d<f32>;
+
+// x: cube face
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = cube_direction(u32(params.x), in.tex_coords);
+    let uv = vec2<f32>(
+        atan2(dir.z, dir.x) / (2.0 * 3.14159265) + 0.5,
+        acos(clamp(dir.y, -1.0, 1.0)) / 3.14159265,
+    );
+
+    // Rgba32Float can't be filtered, take the nearest texel
+    let size = textureDimensions(t_equirect);
+    let texel = min(vec2<i32>(uv * vec2<f32>(size)), size - vec2<i32>(1));
+    return vec4<f32>(textureLoad(t_equirect, texel, 0).rgb, 1.0);
+}
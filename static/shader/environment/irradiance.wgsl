@@ -0,0 +1,41 @@
+#define CUSTOM_INPUT
+#include "common/fullscreen.wgsl"
+#include "common/cube.wgsl"
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+
+// x: cube face
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+// Cosine weighted average of the hemisphere around each direction.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = cube_direction(u32(params.x), in.tex_coords);
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, n));
+    up = cross(n, right);
+
+    let pi = 3.14159265;
+    var irradiance = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var i = 0; i < 64; i = i + 1) {
+        let phi = f32(i) / 64.0 * 2.0 * pi;
+        for (var j = 0; j < 16; j = j + 1) {
+            let theta = (f32(j) + 0.5) / 16.0 * 0.5 * pi;
+            let tangent_dir = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let dir = tangent_dir.x * right + tangent_dir.y * up + tangent_dir.z * n;
+            let color = textureSampleLevel(t_environment, s_environment, dir, 0.0).rgb;
+            irradiance = irradiance + color * cos(theta) * sin(theta);
+            count = count + 1.0;
+        }
+    }
+
+    return vec4<f32>(pi * irradiance / count, 1.0);
+}
@@ -0,0 +1,35 @@
+#define CUSTOM_INPUT
+#include "common/fullscreen.wgsl"
+#include "common/cube.wgsl"
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+
+// x: cube face, y: roughness
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+// Split sum approximation: assume the view direction is the normal and
+// average the reflections GGX would scatter at this roughness.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = cube_direction(u32(params.x), in.tex_coords);
+    let roughness = params.y;
+
+    let sample_count = 128u;
+    var color = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var i = 0u; i < sample_count; i = i + 1u) {
+        let h = importance_sample_ggx(hammersley(i, sample_count), n, roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            color = color + textureSampleLevel(t_environment, s_environment, l, 0.0).rgb * n_dot_l;
+            total = total + n_dot_l;
+        }
+    }
+
+    return vec4<f32>(color / max(total, 0.0001), 1.0);
+}
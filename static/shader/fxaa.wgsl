@@ -0,0 +1,47 @@
+#include "common/fullscreen.wgsl"
+
+// x: span max in texels, y: reduce mul, z: reduce min
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn tap(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(t_input, s_input, uv).rgb;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let center = textureSample(t_input, s_input, uv);
+
+    let luma_nw = luma(tap(uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(tap(uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(tap(uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(tap(uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = luma(center.rgb);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, which is perpendicular to the luma gradient
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * params.y, params.z);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-params.x), vec2<f32>(params.x)) * texel;
+
+    let rgb_a = 0.5 * (tap(uv + dir * (1.0 / 3.0 - 0.5)) + tap(uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (tap(uv - dir * 0.5) + tap(uv + dir * 0.5));
+    let luma_b = luma(rgb_b);
+
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(rgb_a, center.a);
+    }
+    return vec4<f32>(rgb_b, center.a);
+}
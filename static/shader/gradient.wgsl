@@ -0,0 +1,28 @@
+struct Gradient {
+    top: vec4<f32>,
+    bottom: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> gradient: Gradient;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) height: f32,
+};
+
+// Fullscreen triangle on the far plane, like the skybox.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 1.0, 1.0);
+    out.height = (y + 1.0) * 0.5;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return mix(gradient.bottom, gradient.top, in.height);
+}
@@ -1,16 +1,10 @@
 // Vertex shader
 
-struct Camera {
-    view_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-}
+#include "common/camera.wgsl"
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
+#include "common/light.wgsl"
 @group(1) @binding(0)
 var<uniform> light: Light;
 
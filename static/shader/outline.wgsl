@@ -0,0 +1,75 @@
+// Selection outline. `vs_mark` sets the stencil under the selected
+// instances, `vs_outline` draws them again pushed outwards along their
+// normals, and only the part outside the mark shows.
+
+#include "common/camera.wgsl"
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Outline {
+    color: vec4<f32>,
+    // x width in pixels, yz viewport size in pixels
+    size: vec4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> outline: Outline;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+}
+
+fn world_position(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return model_matrix * vec4<f32>(model.position, 1.0);
+}
+
+@vertex
+fn vs_mark(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * world_position(model, instance);
+}
+
+@vertex
+fn vs_outline(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_normal = normal_matrix * model.normal;
+
+    var clip_position = camera.view_proj * world_position(model, instance);
+    // The normal in pixels, so the outline is as wide whatever the depth
+    let screen_normal = (camera.view_proj * vec4<f32>(world_normal, 0.0)).xy * outline.size.yz;
+    if (length(screen_normal) > 0.0) {
+        let offset = normalize(screen_normal) * outline.size.x * 2.0 / outline.size.yz;
+        clip_position = vec4<f32>(clip_position.xy + offset * clip_position.w, clip_position.zw);
+    }
+    return clip_position;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return outline.color;
+}
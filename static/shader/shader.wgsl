@@ -1,25 +1,29 @@
 // Vertex shader
 
-struct Camera {
-    view_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-}
+#include "common/camera.wgsl"
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
+#include "common/light.wgsl"
 @group(2) @binding(0)
 var<uniform> light: Light;
 
+#ifdef SKINNED
+// Joint matrices of the skeleton, see `skinned::MAX_JOINTS`.
+@group(2) @binding(1)
+var<uniform> joints: array<mat4x4<f32>, 128>;
+#endif
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
     @location(3) tangent: vec3<f32>,
     @location(4) bitangent: vec3<f32>,
+#ifdef SKINNED
+    @location(12) joints: vec4<u32>,
+    @location(13) weights: vec4<f32>,
+#endif
 };
 
 struct InstanceInput {
@@ -38,6 +42,12 @@ struct VertexOutput {
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
+#ifdef IBL
+    // Columns of the matrix that takes tangent space back to world space
+    @location(4) world_tangent: vec3<f32>,
+    @location(5) world_bitangent: vec3<f32>,
+    @location(6) world_normal: vec3<f32>,
+#endif
 };
 
 @vertex
@@ -57,17 +67,35 @@ fn vs_main(
         instance.normal_matrix_2,
     );
 
+#ifdef SKINNED
+    // Blend the joints moving the vertex, then place it like any other
+    let skin = joints[model.joints.x] * model.weights.x
+        + joints[model.joints.y] * model.weights.y
+        + joints[model.joints.z] * model.weights.z
+        + joints[model.joints.w] * model.weights.w;
+    let skin_normal = mat3x3<f32>(skin[0].xyz, skin[1].xyz, skin[2].xyz);
+    let position = (skin * vec4<f32>(model.position, 1.0)).xyz;
+    let normal = skin_normal * model.normal;
+    let tangent = skin_normal * model.tangent;
+    let bitangent = skin_normal * model.bitangent;
+#else
+    let position = model.position;
+    let normal = model.normal;
+    let tangent = model.tangent;
+    let bitangent = model.bitangent;
+#endif
+
     // Construct the tangent matrix
-    let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
+    let world_normal = normalize(normal_matrix * normal);
+    let world_tangent = normalize(normal_matrix * tangent);
+    let world_bitangent = normalize(normal_matrix * bitangent);
     let tangent_matrix = transpose(mat3x3<f32>(
         world_tangent,
         world_bitangent,
         world_normal,
     ));
 
-    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
@@ -75,6 +103,11 @@ fn vs_main(
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+#ifdef IBL
+    out.world_tangent = world_tangent;
+    out.world_bitangent = world_bitangent;
+    out.world_normal = world_normal;
+#endif
     return out;
 }
 
@@ -84,22 +117,43 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
+#ifdef NORMAL_MAP
 @group(0)@binding(2)
 var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
+#endif
+// x: dissolve, y: alpha cutoff, z: 1 when blending, see `model::AlphaMode`
+@group(0) @binding(4)
+var<uniform> material: vec4<f32>;
+
+#ifdef IBL
+@group(3) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(2)
+var t_brdf: texture_2d<f32>;
+@group(3) @binding(3)
+var s_ibl: sampler;
+#endif
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+#ifdef NORMAL_MAP
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
-    
+    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
+#else
+    // Without a normal map the surface normal is the tangent space z axis
+    let tangent_normal = vec3<f32>(0.0, 0.0, 1.0);
+#endif
+
     // We don't need (or want) much ambient light, so 0.1 is fine
     let ambient_strength = 0.1;
     let ambient_color = light.color * ambient_strength;
 
     // Create the lighting vectors
-    let tangent_normal = object_normal.xyz * 2.0 - 1.0;
     let light_dir = normalize(in.tangent_light_position - in.tangent_position);
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
     let half_dir = normalize(view_dir + light_dir);
@@ -110,7 +164,46 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
     let specular_color = specular_strength * light.color;
 
+#ifdef IBL
+    // Ambient light comes from the environment instead
+    let tbn = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal),
+    );
+    let n = normalize(tbn * tangent_normal);
+    let v = normalize(tbn * view_dir);
+    let n_dot_v = max(dot(n, v), 0.0);
+
+    // A dielectric about as rough as the shininess of 32 above. The
+    // prefiltered map has 5 mip levels, see `environment::PREFILTER_MIPS`.
+    let roughness = 0.25;
+    let max_reflection_lod = 4.0;
+    let f0 = vec3<f32>(0.04);
+    let fresnel = f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+
+    let irradiance = textureSample(t_irradiance, s_ibl, n).rgb;
+    let prefiltered = textureSampleLevel(
+        t_prefiltered,
+        s_ibl,
+        reflect(-v, n),
+        roughness * max_reflection_lod
+    ).rgb;
+    let brdf = textureSample(t_brdf, s_ibl, vec2<f32>(n_dot_v, roughness)).rg;
+
+    let ambient = (1.0 - fresnel) * irradiance * object_color.xyz
+        + prefiltered * (fresnel * brdf.x + brdf.y);
+    let result = ambient + (diffuse_color + specular_color) * object_color.xyz;
+#else
     let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+#endif
+
+    // Discarding before the other samples would break their uniformity
+    let alpha = object_color.a * material.x;
+    if (alpha < material.y) {
+        discard;
+    }
 
-    return vec4<f32>(result, object_color.a);
+    // Anything that doesn't blend is fully opaque
+    return vec4<f32>(result, mix(1.0, alpha, material.z));
 }
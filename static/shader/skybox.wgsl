@@ -0,0 +1,33 @@
+#include "common/camera.wgsl"
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(1) @binding(1)
+var s_environment: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) clip: vec2<f32>,
+};
+
+// Fullscreen triangle on the far plane, so it only shows where nothing
+// else was drawn.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let x = f32(i32(vertex_index & 1u) * 4 - 1);
+    let y = f32(i32(vertex_index >> 1u) * 4 - 1);
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(x, y, 1.0, 1.0);
+    out.clip = vec2<f32>(x, y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let far = camera.inv_view_proj * vec4<f32>(in.clip, 1.0, 1.0);
+    let dir = normalize(far.xyz / far.w - camera.view_pos.xyz);
+    return vec4<f32>(textureSample(t_environment, s_environment, dir).rgb, 1.0);
+}
@@ -0,0 +1,35 @@
+#include "common/fullscreen.wgsl"
+
+// x: exposure, y: mode, keep in sync with `hdr::Tonemapping`
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(x: vec3<f32>) -> vec3<f32> {
+    return x / (x + vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_input, s_input, in.tex_coords);
+    let color = hdr.rgb * params.x;
+    let mode = u32(params.y);
+
+    var mapped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    if (mode == 1u) {
+        mapped = reinhard(color);
+    } else if (mode == 2u) {
+        mapped = aces(color);
+    }
+
+    return vec4<f32>(mapped, hdr.a);
+}
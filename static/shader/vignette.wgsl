@@ -0,0 +1,14 @@
+#include "common/fullscreen.wgsl"
+
+// x: intensity, y: radius, z: smoothness
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let distance = length(in.tex_coords - vec2<f32>(0.5)) * 1.41421356;
+    let shade = 1.0 - smoothstep(params.y, params.y + params.z, distance) * params.x;
+
+    return vec4<f32>(color.rgb * shade, color.a);
+}